@@ -0,0 +1,584 @@
+use std::f64;
+use std::io::{stderr, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rand::Rng;
+use rayon::prelude::*;
+use super::vec::{Vec3, Point3, Color};
+use super::ray::Ray;
+use super::hit::{Hittable, HitRecord};
+use super::mat::ScatterRecord;
+use super::pdf::power_heuristic;
+use super::camera::Camera;
+use super::film::Film;
+use super::SceneRefs;
+use super::interior::InteriorStack;
+use super::medium::Medium;
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface
+}
+
+struct Vertex<'a> {
+    kind: VertexKind,
+    position: Point3,
+    // geometric normal, facing the side the vertex was reached from
+    normal: Vec3,
+    // direction of the ray arriving at the vertex
+    incoming: Vec3,
+    rec: Option<HitRecord<'a>>,
+    // false inside participating media, where densities carry no cosine
+    on_surface: bool,
+    // the medium inside the dielectrics around the vertex, lights and the camera are outside of all of them
+    medium: Option<&'a dyn Medium>,
+    // ratio of indices of refraction the surface scattered with, as seen from incoming
+    eta: f64,
+    // reached or left through a specular bounce, so it can not be connected to
+    delta: bool,
+    beta: Color,
+    // area densities of sampling the vertex from its own subpath and from the other one
+    pdf_fwd: f64,
+    pdf_rev: f64
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(position: Point3, normal: Vec3) -> Vertex<'a> {
+        Vertex {
+            kind: VertexKind::Camera,
+            position,
+            normal,
+            incoming: Vec3::new(0.0, 0.0, 0.0),
+            rec: None,
+            on_surface: true,
+            medium: None,
+            eta: 1.0,
+            delta: false,
+            beta: Color::new(1.0, 1.0, 1.0),
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0
+        }
+    }
+
+    fn light(rec: HitRecord<'a>, beta: Color, pdf_fwd: f64) -> Vertex<'a> {
+        Vertex {
+            kind: VertexKind::Light,
+            position: rec.position,
+            normal: rec.normal,
+            incoming: Vec3::new(0.0, 0.0, 0.0),
+            rec: Some(rec),
+            on_surface: true,
+            medium: None,
+            eta: 1.0,
+            delta: false,
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.0
+        }
+    }
+
+    // converts a solid angle density at this vertex to an area density at next
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.position - self.position;
+        let distance_squared = w.length().powi(2);
+        if distance_squared == 0.0 {
+            return 0.0
+        }
+        let cosine = if next.on_surface { next.normal.dot(w).abs() / distance_squared.sqrt() } else { 1.0 };
+        pdf * cosine / distance_squared
+    }
+
+    // brdf times cosine (phase function in media) for light going from outgoing back along incoming, with the density of sampling outgoing
+    fn scattering(&self, incoming: Vec3, outgoing: Vec3, time: f64) -> (Color, f64) {
+        let rec = match &self.rec {
+            Some(rec) => rec,
+            None => return (Color::new(0.0, 0.0, 0.0), 0.0)
+        };
+        let r_in = Ray::new(self.position - incoming, incoming, time);
+        let scattered = Ray::new(self.position, outgoing.normalized(), time);
+        match rec.material.scatter_interface(&r_in, rec, self.eta) {
+            Some(ScatterRecord::Scatter { pdf, attenuation }) | Some(ScatterRecord::Volume { pdf, attenuation }) =>
+                (attenuation * rec.material.scattering_pdf(&r_in, rec, &scattered), pdf.value(scattered.direction())),
+            Some(ScatterRecord::Microfacet { pdf, eta }) =>
+                (rec.material.bsdf(&r_in, &scattered, rec, eta) * rec.normal.dot(scattered.direction()).abs(), pdf.value(scattered.direction())),
+            _ => (Color::new(0.0, 0.0, 0.0), 0.0)
+        }
+    }
+
+    // radiance leaving a light vertex along direction, media glow the same way in every direction
+    fn emitted(&self, direction: Vec3, time: f64) -> Color {
+        let rec = match &self.rec {
+            Some(rec) => rec,
+            None => return Color::new(0.0, 0.0, 0.0)
+        };
+        let direction = direction.normalized();
+        let r_in = Ray::new(self.position + direction, (-1.0) * direction, time);
+        if !self.on_surface || direction.dot(rec.normal) >= 0.0 {
+            rec.material.emitted(&r_in, rec)
+        } else if rec.material.emits_both_sides() {
+            // seen from behind the side the record was made for
+            let back = HitRecord {
+                position: rec.position,
+                normal: (-1.0) * rec.normal,
+                t: rec.t,
+                u: rec.u,
+                v: rec.v,
+                front_face: !rec.front_face,
+                weight: rec.weight,
+                material: rec.material
+            };
+            rec.material.emitted(&r_in, &back)
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    // what the vertex passes on toward point, per unit of light or importance arriving at it
+    fn f(&self, point: Point3, time: f64) -> Color {
+        let direction = point - self.position;
+        match self.kind {
+            VertexKind::Light => self.emitted(direction, time) * self.normal.dot(direction.normalized()).abs(),
+            VertexKind::Surface => self.scattering(self.incoming, direction, time).0,
+            VertexKind::Camera => Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    // area density at next of emitting light from this vertex toward it
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let rec = match &self.rec {
+            Some(rec) => rec,
+            None => return 0.0
+        };
+        let side = if rec.material.emits_both_sides() { 0.5 } else { 1.0 };
+        let cosine = self.normal.dot((next.position - self.position).normalized()).abs();
+        self.convert_density(side * cosine / f64::consts::PI, next)
+    }
+
+    // area density of starting a light path at this vertex, lights are chosen proportional to their power
+    fn pdf_light_origin(&self, lights_power: f64) -> f64 {
+        match &self.rec {
+            Some(rec) if lights_power > 0.0 => {
+                let sides = if rec.material.emits_both_sides() { 2.0 } else { 1.0 };
+                sides * f64::consts::PI * rec.material.emitted_luminance() / lights_power
+            },
+            _ => 0.0
+        }
+    }
+
+    // area density at next of sampling it from this vertex, after arriving from prev
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex, time: f64) -> f64 {
+        let direction = next.position - self.position;
+        let pdf = match (self.kind, prev) {
+            (VertexKind::Camera, _) => camera.pdf_direction(self.position, direction),
+            (VertexKind::Light, _) => return self.pdf_light(next),
+            (VertexKind::Surface, Some(prev)) => self.scattering(self.position - prev.position, direction, time).1,
+            (VertexKind::Surface, None) => 0.0
+        };
+        self.convert_density(pdf, next)
+    }
+}
+
+// densities and delta flag of a vertex as seen by the weight of one strategy
+#[derive(Clone, Copy)]
+struct Pdfs {
+    fwd: f64,
+    rev: f64,
+    delta: bool
+}
+
+impl Pdfs {
+    fn of(vertex: &Vertex) -> Pdfs {
+        Pdfs {
+            fwd: vertex.pdf_fwd,
+            rev: vertex.pdf_rev,
+            delta: vertex.delta
+        }
+    }
+}
+
+// densities of delta vertices are zero, they cancel in the ratios
+fn remap0(pdf: f64) -> f64 {
+    if pdf != 0.0 { pdf } else { 1.0 }
+}
+
+// bidirectional path tracer, connects every prefix of a camera path with every prefix of a light path
+// point, spot and directional lights and the background are only reached from the camera side
+pub struct BidirectionalPathTracer<'a> {
+    camera: &'a Camera,
+    scene: SceneRefs<'a>,
+    // longest path in bounces
+    max_depth: usize,
+    lights_power: f64,
+    // environment lights or an empty light list, light sampling then also looks for the background
+    infinite_lights: bool
+}
+
+impl<'a> BidirectionalPathTracer<'a> {
+    pub fn new(camera: &'a Camera, scene: SceneRefs<'a>, max_depth: usize) -> BidirectionalPathTracer<'a> {
+        BidirectionalPathTracer {
+            camera,
+            scene,
+            max_depth,
+            lights_power: scene.lights.power(),
+            infinite_lights: scene.lights.bounding_box(0.0, 1.0).is_none()
+        }
+    }
+
+    pub fn render(&self, width: usize, height: usize, samples_per_pixel: u64) -> Film {
+        let remaining = AtomicUsize::new(height);
+        (0..height).into_par_iter().fold(|| Film::new(width, height), |mut film, j| {
+            let mut rng = rand::thread_rng();
+            for i in 0..width {
+                for _ in 0..samples_per_pixel {
+                    let s = (i as f64 + rng.gen::<f64>()) / width as f64;
+                    let t = (j as f64 + rng.gen::<f64>()) / height as f64;
+                    let color = self.sample(s, t, &mut film);
+                    film.add(i, j, color);
+                }
+            }
+            eprint!("\rScanlines remaining: {:3}", remaining.fetch_sub(1, Ordering::Relaxed) - 1);
+            stderr().flush().unwrap();
+            film
+        })
+        .reduce(|| Film::new(width, height), Film::merge)
+    }
+
+    // radiance through film coordinates (s, t), light tracing contributions are splatted onto the film
+    fn sample(&self, s: f64, t: f64, film: &mut Film) -> Color {
+        let ray = self.camera.get_ray(s, t);
+        let time = ray.time();
+
+        let mut camera_path = vec![Vertex::camera(ray.origin(), self.camera.normal())];
+        let pdf = self.camera.pdf_direction(ray.origin(), ray.direction());
+        let direction = ray.direction().normalized();
+        let escaped = self.random_walk(Ray::new(ray.origin(), direction, time), Color::new(1.0, 1.0, 1.0), pdf, self.max_depth + 2, &mut camera_path);
+
+        // the background is only found by escaping camera rays and light sampling
+        let mut color = match escaped {
+            Some((ray, beta, Some(bsdf_pdf))) if self.infinite_lights => {
+                let weight = power_heuristic(bsdf_pdf, self.scene.lights.pdf_value(ray.origin(), ray.direction()));
+                beta * self.scene.background.color(&ray) * weight
+            },
+            Some((ray, beta, _)) => beta * self.scene.background.color(&ray),
+            None => Color::new(0.0, 0.0, 0.0)
+        };
+
+        let light_path = self.light_subpath(time);
+
+        for t in 1..=camera_path.len() {
+            if t >= 2 && t - 1 <= self.max_depth {
+                color += self.delta_lights_color(&camera_path[t - 1], time);
+                color += self.background_color(&camera_path[t - 1], time);
+            }
+            for s in 0..=light_path.len() {
+                if s + t < 2 || s + t - 2 > self.max_depth {
+                    continue
+                }
+                color += self.connect(&light_path, &camera_path, s, t, time, film);
+            }
+        }
+
+        color
+    }
+
+    fn light_subpath(&self, time: f64) -> Vec<Vertex<'a>> {
+        let mut path = Vec::new();
+        let lights: &'a dyn Hittable = self.scene.lights;
+        let emitted = match lights.sample_emitted_ray(time) {
+            Some(emitted) if emitted.pdf_position > 0.0 && !emitted.radiance.near_zero() => emitted,
+            _ => return path
+        };
+        let cosine = emitted.rec.normal.dot(emitted.ray.direction()).abs();
+        let beta = emitted.radiance * cosine / (emitted.pdf_position * emitted.pdf_direction);
+        let vertex = Vertex::light(emitted.rec, Color::new(1.0, 1.0, 1.0) / emitted.pdf_position, 0.0);
+        let pdf_fwd = vertex.pdf_light_origin(self.lights_power);
+        path.push(Vertex { pdf_fwd, ..vertex });
+        self.random_walk(emitted.ray, beta, emitted.pdf_direction, self.max_depth + 1, &mut path);
+        path
+    }
+
+    // extends path by bsdf sampling, returns the ray that left the scene with its throughput and bsdf density
+    fn random_walk(&self, mut ray: Ray, mut beta: Color, mut pdf: f64, max_vertices: usize, path: &mut Vec<Vertex<'a>>) -> Option<(Ray, Color, Option<f64>)> {
+        let world: &'a dyn Hittable = self.scene.world;
+        let time = ray.time();
+        // None after the camera and specular bounces, which light sampling can not compete with
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut stack = InteriorStack::default();
+
+        while path.len() < max_vertices {
+            let rec = match stack.hit(world, &ray) {
+                Some(rec) => rec,
+                // light pdfs are measured from the last vertex, crossing media may have moved the ray on
+                None => return Some((Ray::new(path[path.len() - 1].position, ray.direction(), time), beta, bsdf_pdf))
+            };
+            beta = beta * rec.weight;
+            let srec = stack.scatter(&ray, &rec);
+            // crossing a medium makes no vertex, densities still convert from the one before
+            if let Some(ScatterRecord::PassThrough { ray: through }) = srec {
+                ray = through;
+                continue
+            }
+            let mut vertex = Vertex {
+                kind: VertexKind::Surface,
+                position: rec.position,
+                normal: rec.normal,
+                incoming: ray.direction(),
+                on_surface: !matches!(srec, Some(ScatterRecord::Volume { .. })),
+                medium: stack.medium(),
+                eta: if let Some(ScatterRecord::Microfacet { eta, .. }) = srec { eta } else { 1.0 },
+                rec: Some(rec),
+                delta: false,
+                beta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0
+            };
+            let n = path.len();
+            vertex.pdf_fwd = path[n - 1].convert_density(pdf, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break
+            }
+
+            let n = path.len();
+            let scattered = match srec {
+                Some(ScatterRecord::Specular { specular_ray, attenuation }) => {
+                    path[n - 1].delta = true;
+                    path[n - 2].pdf_rev = 0.0;
+                    beta = beta * attenuation;
+                    pdf = 0.0;
+                    bsdf_pdf = None;
+                    specular_ray
+                },
+                Some(ScatterRecord::Scatter { pdf: bsdf, .. }) | Some(ScatterRecord::Microfacet { pdf: bsdf, .. }) | Some(ScatterRecord::Volume { pdf: bsdf, .. }) => {
+                    let current = &path[n - 1];
+                    let direction = match bsdf.generate() {
+                        Some(direction) => direction.normalized(),
+                        None => break
+                    };
+                    let (f, pdf_fwd) = current.scattering(ray.direction(), direction, time);
+                    if f.near_zero() || pdf_fwd <= 0.0 {
+                        break
+                    }
+                    let (_, pdf_rev) = current.scattering((-1.0) * direction, (-1.0) * ray.direction(), time);
+                    let rev = current.convert_density(pdf_rev, &path[n - 2]);
+                    path[n - 2].pdf_rev = rev;
+                    beta = beta * f / pdf_fwd;
+                    pdf = pdf_fwd;
+                    bsdf_pdf = Some(pdf_fwd);
+                    Ray::new(path[n - 1].position, direction, time)
+                },
+                _ => break
+            };
+            if let Some(rec) = &path[n - 1].rec {
+                stack.cross(rec, scattered.direction());
+            }
+            ray = Ray::new(scattered.origin(), scattered.direction().normalized(), time);
+        }
+
+        None
+    }
+
+    // a and b see each other through the same medium, surfaces between them are dielectrics and block the way
+    fn transmittance(&self, a: Point3, b: Point3, medium: Option<&dyn Medium>, time: f64) -> Color {
+        let distance = (b - a).length();
+        let ray = Ray::new(a, (b - a) / distance, time);
+        let t_max = distance * (1.0 - 1e-6);
+        self.scene.world.transmittance(&ray, 0.00001, t_max) * super::medium_transmittance(medium, &ray, 0.00001, t_max)
+    }
+
+    // whether the emitter hit by ray is one of the lights, others are only found by camera paths
+    fn is_light(&self, ray: &Ray, rec: &HitRecord) -> bool {
+        self.scene.lights.hit(ray, 0.00001, f64::INFINITY).is_some_and(|light| (light.position - rec.position).length() <= 1e-7 * light.t.max(1.0))
+    }
+
+    fn delta_lights_color(&self, vertex: &Vertex, time: f64) -> Color {
+        match &vertex.rec {
+            Some(rec) if !vertex.delta => {
+                let ray = Ray::new(vertex.position - vertex.incoming, vertex.incoming, time);
+                let f_cos = |scattered: &Ray| vertex.scattering(vertex.incoming, scattered.direction(), time).0;
+                vertex.beta * super::delta_light_color(&ray, rec, &self.scene, vertex.medium, &f_cos)
+            },
+            _ => Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    // light sampling toward the background, weighted against escaping camera rays
+    fn background_color(&self, vertex: &Vertex, time: f64) -> Color {
+        let zero = Color::new(0.0, 0.0, 0.0);
+        if !self.infinite_lights || vertex.delta {
+            return zero
+        }
+        let direction = self.scene.lights.random(vertex.position).normalized();
+        let light_pdf = self.scene.lights.pdf_value(vertex.position, direction);
+        if light_pdf <= 0.0 {
+            return zero
+        }
+        let (f, bsdf_pdf) = vertex.scattering(vertex.incoming, direction, time);
+        let ray = Ray::new(vertex.position, direction, time);
+        // lights inside the scene are left to the connections
+        if f.near_zero() || self.scene.lights.hit(&ray, 0.00001, f64::INFINITY).is_some() {
+            return zero
+        }
+        let transmittance = self.scene.world.transmittance(&ray, 0.00001, f64::INFINITY);
+        vertex.beta * f * self.scene.background.color(&ray) * transmittance * power_heuristic(light_pdf, bsdf_pdf) / light_pdf
+    }
+
+    // contribution of the path made of s light and t camera vertices
+    fn connect(&self, light_path: &[Vertex<'a>], camera_path: &[Vertex<'a>], s: usize, t: usize, time: f64, film: &mut Film) -> Color {
+        let zero = Color::new(0.0, 0.0, 0.0);
+
+        if s == 0 {
+            // the camera path found a light by itself
+            let pt = &camera_path[t - 1];
+            let rec = match &pt.rec {
+                Some(rec) => rec,
+                None => return zero
+            };
+            let radiance = pt.beta * pt.emitted((-1.0) * pt.incoming, time);
+            if radiance.near_zero() {
+                return zero
+            }
+            let ray = Ray::new(camera_path[t - 2].position, pt.incoming, time);
+            if !self.is_light(&ray, rec) {
+                return radiance
+            }
+            return radiance * self.mis_weight(light_path, camera_path, None, s, t, time)
+        }
+
+        if t == 1 {
+            // light tracing, the light path is connected to the lens
+            let qs = &light_path[s - 1];
+            if qs.delta {
+                return zero
+            }
+            let sample = match self.camera.sample_wi(qs.position) {
+                Some(sample) => sample,
+                None => return zero
+            };
+            let color = qs.beta * qs.f(sample.lens, time) * sample.importance / sample.pdf;
+            if color.near_zero() {
+                return zero
+            }
+            let color = color * self.transmittance(qs.position, sample.lens, qs.medium, time);
+            if color.near_zero() {
+                return zero
+            }
+            let lens = Vertex::camera(sample.lens, self.camera.normal());
+            let weight = self.mis_weight(light_path, camera_path, Some(&lens), s, t, time);
+            film.splat(sample.s, sample.t, color * weight);
+            return zero
+        }
+
+        let pt = &camera_path[t - 1];
+        if pt.delta {
+            return zero
+        }
+
+        if s == 1 {
+            // a point on a light, chosen the same way light paths start
+            let surface = match self.scene.lights.sample_surface(time) {
+                Some(surface) if surface.pdf > 0.0 => surface,
+                _ => return zero
+            };
+            let distance = (surface.position - pt.position).length();
+            let direction = (surface.position - pt.position) / distance;
+            let f = pt.f(surface.position, time);
+            if f.near_zero() {
+                return zero
+            }
+            // the light has to be the first light along the way, whatever else is there dims it
+            let lights: &'a dyn Hittable = self.scene.lights;
+            let ray = Ray::new(pt.position, direction, time);
+            let rec = match lights.hit(&ray, 0.00001, f64::INFINITY) {
+                Some(rec) if (rec.t - distance).abs() <= 1e-6 * distance => rec,
+                _ => return zero
+            };
+            let cosine = rec.normal.dot(direction).abs();
+            let color = pt.beta * f * rec.material.emitted(&ray, &rec) * cosine / (distance.powi(2) * surface.pdf);
+            if color.near_zero() {
+                return zero
+            }
+            let t_max = distance * (1.0 - 1e-6);
+            let color = color * self.scene.world.transmittance(&ray, 0.00001, t_max) * super::medium_transmittance(pt.medium, &ray, 0.00001, t_max);
+            if color.near_zero() {
+                return zero
+            }
+            let light = Vertex::light(rec, Color::new(1.0, 1.0, 1.0) / surface.pdf, 0.0);
+            let pdf_fwd = light.pdf_light_origin(self.lights_power);
+            let light = Vertex { pdf_fwd, ..light };
+            return color * self.mis_weight(light_path, camera_path, Some(&light), s, t, time)
+        }
+
+        let qs = &light_path[s - 1];
+        if qs.delta {
+            return zero
+        }
+        let distance_squared = (pt.position - qs.position).length().powi(2);
+        let color = qs.beta * qs.f(pt.position, time) * pt.f(qs.position, time) * pt.beta / distance_squared;
+        if color.near_zero() {
+            return zero
+        }
+        let color = color * self.transmittance(pt.position, qs.position, pt.medium, time);
+        if color.near_zero() {
+            return zero
+        }
+        color * self.mis_weight(light_path, camera_path, None, s, t, time)
+    }
+
+    // power heuristic over every way the same path could have been sampled, computed from ratios of densities
+    fn mis_weight(&self, light_path: &[Vertex<'a>], camera_path: &[Vertex<'a>], sampled: Option<&Vertex<'a>>, s: usize, t: usize, time: f64) -> f64 {
+        // the sampled vertex replaces the end of the subpath of length one, the lens when both have length one
+        let light: Vec<&Vertex> = match sampled {
+            Some(vertex) if s == 1 && t > 1 => vec![vertex],
+            _ => light_path[..s].iter().collect()
+        };
+        let camera: Vec<&Vertex> = match sampled {
+            Some(vertex) if t == 1 => vec![vertex],
+            _ => camera_path[..t].iter().collect()
+        };
+        let mut light_pdfs: Vec<Pdfs> = light.iter().map(|v| Pdfs::of(v)).collect();
+        let mut camera_pdfs: Vec<Pdfs> = camera.iter().map(|v| Pdfs::of(v)).collect();
+
+        // densities of the connection endpoints being sampled from the other side
+        let pt = camera[t - 1];
+        let pt_minus = if t > 1 { Some(camera[t - 2]) } else { None };
+        camera_pdfs[t - 1].delta = false;
+        if s > 0 {
+            let qs = light[s - 1];
+            let qs_minus = if s > 1 { Some(light[s - 2]) } else { None };
+            light_pdfs[s - 1].delta = false;
+            camera_pdfs[t - 1].rev = qs.pdf(self.camera, qs_minus, pt, time);
+            if let Some(pt_minus) = pt_minus {
+                camera_pdfs[t - 2].rev = pt.pdf(self.camera, Some(qs), pt_minus, time);
+            }
+            light_pdfs[s - 1].rev = pt.pdf(self.camera, pt_minus, qs, time);
+            if let Some(qs_minus) = qs_minus {
+                light_pdfs[s - 2].rev = qs.pdf(self.camera, Some(pt), qs_minus, time);
+            }
+        } else {
+            camera_pdfs[t - 1].rev = pt.pdf_light_origin(self.lights_power);
+            if let Some(pt_minus) = pt_minus {
+                camera_pdfs[t - 2].rev = pt.pdf_light(pt_minus);
+            }
+        }
+
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera_pdfs[i].rev) / remap0(camera_pdfs[i].fwd);
+            if !camera_pdfs[i].delta && !camera_pdfs[i - 1].delta {
+                sum += ri * ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light_pdfs[i].rev) / remap0(light_pdfs[i].fwd);
+            let delta_light = i > 0 && light_pdfs[i - 1].delta;
+            if !light_pdfs[i].delta && !delta_light {
+                sum += ri * ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
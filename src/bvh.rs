@@ -3,6 +3,7 @@ use super::aabb;
 use super::aabb::AABB;
 use super::hit::{Hittable, HitRecord};
 use super::ray::Ray;
+use super::vec::{Vec3, Point3, Color};
 
 enum BVHNode {
     Branch { left: Box<BVH>, right: Box<BVH> },
@@ -71,6 +72,18 @@ impl BVH {
         }
 
     }
+
+    // sum of the pdf values of the leaves along a direction, for leaves that carry their own chance of being sampled.
+    // leaves whose boxes the direction misses contribute nothing, so only they are skipped
+    pub fn pdf_sum(&self, o: Point3, v: Vec3) -> f64 {
+        if !self.bbox.hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY) {
+            return 0.0
+        }
+        match &self.tree {
+            BVHNode::Branch { left, right } => left.pdf_sum(o, v) + right.pdf_sum(o, v),
+            BVHNode::Leaf(leaf) => leaf.pdf_value(o, v)
+        }
+    }
 }
 
 impl Hittable for BVH {
@@ -90,7 +103,27 @@ impl Hittable for BVH {
         }
     }
 
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return Color::new(1.0, 1.0, 1.0)
+        }
+        match &self.tree {
+            BVHNode::Branch { left, right } => {
+                let left = left.transmittance(r, t_min, t_max);
+                if left.near_zero() { left } else { left * right.transmittance(r, t_min, t_max) }
+            },
+            BVHNode::Leaf(leaf) => leaf.transmittance(r, t_min, t_max)
+        }
+    }
+
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         Some(self.bbox)
     }
+
+    fn power(&self) -> f64 {
+        match &self.tree {
+            BVHNode::Branch { left, right } => left.power() + right.power(),
+            BVHNode::Leaf(leaf) => leaf.power()
+        }
+    }
 }
\ No newline at end of file
@@ -3,6 +3,17 @@ use std::f64;
 use super::vec::{Vec3, Point3};
 use super::ray::Ray;
 
+// importance a sampled lens point sends toward a point in the scene
+pub struct CameraSample {
+    pub lens: Point3,
+    // film coordinates in [0, 1)^2, as taken by get_ray
+    pub s: f64,
+    pub t: f64,
+    pub importance: f64,
+    // solid angle density of the lens point seen from the scene
+    pub pdf: f64
+}
+
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -10,6 +21,7 @@ pub struct Camera {
     vertical: Vec3,
     cu: Vec3,
     cv: Vec3,
+    cw: Vec3,
     lens_radius: f64,
     time0: f64,
     time1: f64
@@ -42,6 +54,7 @@ impl Camera {
             lower_left_corner: llc,
             cu,
             cv,
+            cw,
             lens_radius: aperture / 2.0,
             time0,
             time1
@@ -53,8 +66,69 @@ impl Camera {
         let offset = self.cu * rd.x() + self.cv * rd.y();
 
         //track time between the time of strat and end
-        let time = self.time0 + rand::thread_rng().gen::<f64>() * (self.time1 - self.time0);
+        let time = self.time();
 
         Ray::new(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - (self.origin + offset), time)
     }
-}
\ No newline at end of file
+
+    // random time while the shutter is open
+    pub fn time(&self) -> f64 {
+        self.time0 + rand::thread_rng().gen::<f64>() * (self.time1 - self.time0)
+    }
+
+    // normal of the lens, looking into the scene
+    pub fn normal(&self) -> Vec3 {
+        (-1.0) * self.cw
+    }
+
+    // area of the film scaled to unit distance from the lens
+    fn film_area(&self) -> f64 {
+        let focus_dist = (self.origin - self.lower_left_corner).dot(self.cw);
+        self.horizontal.length() * self.vertical.length() / focus_dist.powi(2)
+    }
+
+    fn lens_area(&self) -> f64 {
+        if self.lens_radius > 0.0 { f64::consts::PI * self.lens_radius.powi(2) } else { 1.0 }
+    }
+
+    // film coordinates of a ray leaving the lens point, None when it misses the film
+    fn raster(&self, lens: Point3, direction: Vec3) -> Option<(f64, f64)> {
+        if direction.dot(self.cw) >= 0.0 {
+            return None
+        }
+        // the film lies in the plane of focus
+        let k = (self.lower_left_corner - lens).dot(self.cw) / direction.dot(self.cw);
+        let offset = lens + k * direction - self.lower_left_corner;
+        let s = offset.dot(self.horizontal) / self.horizontal.length().powi(2);
+        let t = offset.dot(self.vertical) / self.vertical.length().powi(2);
+        if (0.0..1.0).contains(&s) && (0.0..1.0).contains(&t) { Some((s, t)) } else { None }
+    }
+
+    // solid angle density of get_ray producing direction from the lens point
+    pub fn pdf_direction(&self, lens: Point3, direction: Vec3) -> f64 {
+        let direction = direction.normalized();
+        if self.raster(lens, direction).is_none() {
+            return 0.0
+        }
+        let cos_theta = -direction.dot(self.cw);
+        1.0 / (self.film_area() * cos_theta.powi(3))
+    }
+
+    // picks a lens point for a point in the scene and finds where its light lands on the film
+    pub fn sample_wi(&self, point: Point3) -> Option<CameraSample> {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+        let lens = self.origin + self.cu * rd.x() + self.cv * rd.y();
+        let distance = (point - lens).length();
+        let direction = (point - lens) / distance;
+        let (s, t) = self.raster(lens, direction)?;
+        let cos_theta = -direction.dot(self.cw);
+        Some(CameraSample {
+            lens,
+            s,
+            t,
+            // normalized so that the importance over the film and lens integrates to one
+            importance: 1.0 / (self.film_area() * self.lens_area() * cos_theta.powi(4)),
+            pdf: distance.powi(2) / (cos_theta * self.lens_area())
+        })
+    }
+}
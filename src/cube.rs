@@ -1,8 +1,9 @@
 use super::vec::{Point3};
-use super::hit::{Hittable, HitRecord, HittableList};
+use super::hit::{Hittable, HitRecord, HittableList, SurfaceSample, FlipNormal};
 use super::mat::{Material};
 use super::rect::{Plane, AARect};
 use super::aabb::AABB;
+use super::light_bvh::LightBounds;
 
 pub struct Cube {
     min: Point3,
@@ -14,14 +15,16 @@ impl Cube {
     pub fn new<M: Material + Clone + 'static>(min: Point3, max: Point3, material: M) -> Cube {
         let mut sides = HittableList::default();
 
+        // rectangles face along their axis, the sides at min are flipped so that every front face is outside.
+        // dielectrics and the interior stack tell entering from leaving by front_face
         sides.push(AARect::new(Plane::XY, min.x(), max.x(), min.y(), max.y(), max.z(), material.clone()));
-        sides.push(AARect::new(Plane::XY, min.x(), max.x(), min.y(), max.y(), min.z(), material.clone()));
+        sides.push(FlipNormal::new(AARect::new(Plane::XY, min.x(), max.x(), min.y(), max.y(), min.z(), material.clone())));
 
         sides.push(AARect::new(Plane::XZ, min.x(), max.x(), min.z(), max.z(), max.y(), material.clone()));
-        sides.push(AARect::new(Plane::XZ, min.x(), max.x(), min.z(), max.z(), min.y(), material.clone()));
+        sides.push(FlipNormal::new(AARect::new(Plane::XZ, min.x(), max.x(), min.z(), max.z(), min.y(), material.clone())));
 
         sides.push(AARect::new(Plane::YZ, min.y(), max.y(), min.z(), max.z(), max.x(), material.clone()));
-        sides.push(AARect::new(Plane::YZ, min.y(), max.y(), min.z(), max.z(), min.x(), material));
+        sides.push(FlipNormal::new(AARect::new(Plane::YZ, min.y(), max.y(), min.z(), max.z(), min.x(), material)));
 
         Cube {
             min,
@@ -36,6 +39,18 @@ impl Hittable for Cube {
         self.sides.hit(r, t_min, t_max)
     }
 
+    fn power(&self) -> f64 {
+        self.sides.power()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        self.sides.light_bounds()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        self.sides.sample_surface(time)
+    }
+
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         Some(
             AABB {
@@ -44,4 +59,30 @@ impl Hittable for Cube {
             }
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::{Vec3, Color};
+    use crate::ray::Ray;
+    use crate::mat::Lambertian;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn cube_faces_outward_on_every_side() {
+        let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+        let cube = Cube::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0), white);
+        let center = Point3::new(0.0, 0.0, 0.0);
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut direction = Vec3::new(0.0, 0.0, 0.0);
+                direction[axis] = sign;
+                let leaving = cube.hit(&Ray::new(center, direction, 0.0), 0.001, f64::INFINITY).unwrap();
+                assert!(!leaving.front_face, "leaving along axis {} toward {} is a front face", axis, sign);
+                let entering = cube.hit(&Ray::new(center + 3.0 * direction, (-1.0) * direction, 0.0), 0.001, f64::INFINITY).unwrap();
+                assert!(entering.front_face, "entering along axis {} from {} is a back face", axis, sign);
+            }
+        }
+    }
+}
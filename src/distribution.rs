@@ -0,0 +1,169 @@
+// piecewise constant 1D distribution sampled by inverting its CDF
+#[derive(Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f64;
+        }
+
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            // fall back to a uniform distribution when every value is zero
+            *c = if integral == 0.0 { i as f64 / n as f64 } else { *c / integral };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    fn find_interval(&self, u: f64) -> usize {
+        // last cdf entry not greater than u
+        let i = self.cdf.partition_point(|&c| c <= u);
+        i.saturating_sub(1).min(self.count() - 1)
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// returns a value in [0, 1), its density and the index of the segment it falls in
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.find_interval(u);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        let x = (offset as f64 + du) / self.count() as f64;
+        (x, self.pdf(offset), offset)
+    }
+
+    // density of the continuous distribution in the given segment
+    pub fn pdf(&self, index: usize) -> f64 {
+        if self.integral == 0.0 { 1.0 } else { self.func[index].abs() / self.integral }
+    }
+
+    /// returns the sampled index and its probability
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let offset = self.find_interval(u);
+        (offset, self.discrete_pdf(offset))
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> f64 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+}
+
+// piecewise constant 2D distribution over [0, 1)^2, a marginal over rows and a conditional per row
+#[derive(Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    // func is stored row by row, nu values per row
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = (0..nv).map(|v| Distribution1D::new(func[v * nu..(v + 1) * nu].to_vec())).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Distribution2D {
+            conditional,
+            marginal
+        }
+    }
+
+    /// returns the sampled point and its density
+    pub fn sample_continuous(&self, u: f64, v: f64) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(v);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        let conditional = &self.conditional[row];
+        let column = ((x * conditional.count() as f64) as usize).min(conditional.count() - 1);
+        if self.marginal.integral() == 0.0 {
+            1.0
+        } else {
+            conditional.func[column].abs() / self.marginal.integral()
+        }
+    }
+}
+
+// Vose's alias method, constant time sampling of a discrete distribution
+#[derive(Clone)]
+pub struct AliasTable {
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+    pmf: Vec<f64>
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f64]) -> AliasTable {
+        let n = weights.len();
+        let total: f64 = weights.iter().sum();
+        let pmf: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w / total).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        let mut prob = vec![1.0; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+        let scaled: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.0);
+        let mut scaled = scaled;
+
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] += scaled[s] - 1.0;
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // leftovers are 1 up to rounding error
+        for i in small.into_iter().chain(large) {
+            prob[i] = 1.0;
+        }
+
+        AliasTable {
+            prob,
+            alias,
+            pmf
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.pmf.len()
+    }
+
+    /// returns the sampled index and its probability
+    pub fn sample(&self, u: f64) -> (usize, f64) {
+        let n = self.count();
+        let scaled = u * n as f64;
+        let i = (scaled as usize).min(n - 1);
+        let up = scaled - i as f64;
+        let index = if up < self.prob[i] { i } else { self.alias[i] };
+        (index, self.pmf[index])
+    }
+
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.pmf[index]
+    }
+}
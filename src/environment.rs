@@ -0,0 +1,148 @@
+use std::f64;
+use std::sync::Arc;
+use std::fs::File;
+use std::io::BufReader;
+use image::codecs::hdr::HdrDecoder;
+use rand::Rng;
+use super::vec::{Vec3, Point3, Color};
+use super::ray::Ray;
+use super::hit::{Hittable, HitRecord};
+use super::aabb::AABB;
+use super::light_bvh::LightBounds;
+use super::distribution::Distribution2D;
+
+// radiance arriving along rays that escape the scene
+pub trait Background: Sync {
+    fn color(&self, ray: &Ray) -> Color;
+    // whether light arrives from the background at all, light and photon paths can not start there
+    fn emits(&self) -> bool { true }
+}
+
+impl Background for Color {
+    fn color(&self, _ray: &Ray) -> Color {
+        *self
+    }
+
+    fn emits(&self) -> bool {
+        !self.near_zero()
+    }
+}
+
+// image::open tone maps .hdr files to 8 bits, so those go through the Radiance decoder directly
+fn load_hdr_image(path: &str) -> (usize, usize, Vec<Color>) {
+    if path.ends_with(".hdr") {
+        let file = File::open(path).expect("environment map not found");
+        let decoder = HdrDecoder::new(BufReader::new(file)).expect("invalid Radiance file");
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr().expect("invalid Radiance file").into_iter()
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        (metadata.width as usize, metadata.height as usize, pixels)
+    } else {
+        let image = image::open(path).expect("environment map not found").into_rgb32f();
+        let pixels = image.pixels().map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+        (image.width() as usize, image.height() as usize, pixels)
+    }
+}
+
+struct EnvironmentImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    // luminance weighted by the solid angle of each row
+    distribution: Distribution2D
+}
+
+// equirectangular HDR environment, +y is up and the image center looks down -x rotated by rotation
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    image: Arc<EnvironmentImage>,
+    intensity: f64,
+    rotation: f64
+}
+
+impl EnvironmentMap {
+    // loads a Radiance .hdr or OpenEXR file, rotation is in degrees around the y axis
+    pub fn new(path: &str, intensity: f64, rotation: f64) -> EnvironmentMap {
+        let (width, height, pixels) = load_hdr_image(path);
+        EnvironmentMap::from_pixels(width, height, pixels, intensity, rotation)
+    }
+
+    // pixels are stored row by row, the first row is straight up
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>, intensity: f64, rotation: f64) -> EnvironmentMap {
+        let func: Vec<f64> = pixels.iter().enumerate().map(|(i, color)| {
+            let theta = f64::consts::PI * ((i / width) as f64 + 0.5) / height as f64;
+            color.luminance() * theta.sin()
+        }).collect();
+
+        EnvironmentMap {
+            image: Arc::new(EnvironmentImage {
+                width,
+                height,
+                pixels,
+                distribution: Distribution2D::new(&func, width, height)
+            }),
+            intensity,
+            rotation: rotation.to_radians()
+        }
+    }
+
+    // image coordinates in [0, 1)^2 of a world direction
+    fn direction_to_uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = direction.normalized();
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = d.z().atan2(d.x()) - self.rotation;
+        let u = (phi / (2.0 * f64::consts::PI)).rem_euclid(1.0);
+        (u, theta / f64::consts::PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let theta = v * f64::consts::PI;
+        let phi = u * 2.0 * f64::consts::PI + self.rotation;
+        Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+
+    fn lookup(&self, direction: Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(direction);
+        let i = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let j = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        self.intensity * self.image.pixels[j * self.image.width + i]
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, ray: &Ray) -> Color {
+        self.lookup(ray.direction())
+    }
+}
+
+// the environment is never hit, it only takes part in light sampling
+impl Hittable for EnvironmentMap {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord> {
+        None
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        None
+    }
+
+    fn pdf_value(&self, _o: Point3, v: Vec3) -> f64 {
+        let (u, w) = self.direction_to_uv(v);
+        let sin_theta = (w * f64::consts::PI).sin();
+        if sin_theta == 0.0 {
+            return 0.0
+        }
+        // change of variables from the image to the sphere of directions
+        self.image.distribution.pdf(u, w) / (2.0 * f64::consts::PI * f64::consts::PI * sin_theta)
+    }
+
+    fn random(&self, _o: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let ((u, v), _) = self.image.distribution.sample_continuous(rng.gen::<f64>(), rng.gen::<f64>());
+        self.uv_to_direction(u, v)
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
@@ -0,0 +1,50 @@
+use super::vec::Color;
+
+// radiance summed per pixel, film coordinates (s, t) in [0, 1)^2 start at the bottom left like Camera::get_ray
+pub struct Film {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); width * height]
+        }
+    }
+
+    pub fn add(&mut self, i: usize, j: usize, color: Color) {
+        self.pixels[j * self.width + i] += color;
+    }
+
+    // light that reached the camera through some other pixel than the one being sampled
+    pub fn splat(&mut self, s: f64, t: f64, color: Color) {
+        let i = ((s * self.width as f64) as usize).min(self.width - 1);
+        let j = ((t * self.height as f64) as usize).min(self.height - 1);
+        self.add(i, j, color);
+    }
+
+    #[cfg(test)]
+    pub fn sum(&self) -> Color {
+        self.pixels.iter().copied().sum()
+    }
+
+    pub fn merge(mut self, other: Film) -> Film {
+        for (pixel, color) in self.pixels.iter_mut().zip(other.pixels) {
+            *pixel += color;
+        }
+        self
+    }
+
+    // ppm pixel rows from the top, the header is written by the caller
+    pub fn write_ppm(&self, samples_per_pixel: u64) {
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                println!("{}", self.pixels[j * self.width + i].format_color(samples_per_pixel));
+            }
+        }
+    }
+}
@@ -1,9 +1,13 @@
-use rand::seq::SliceRandom;
-use super::vec::{Vec3, Point3};
+use std::sync::OnceLock;
+use rand::Rng;
+use super::vec::{Vec3, Point3, Color};
 use super::ray::Ray;
 use super::mat::Material;
+use super::pdf::PDF;
 use super::aabb;
 use super::aabb::AABB;
+use super::distribution::AliasTable;
+use super::light_bvh::LightBounds;
 
 // 'static denotes that the affected reference can live for the entire duration of the program
 pub struct HitRecord<'a> {
@@ -15,6 +19,9 @@ pub struct HitRecord<'a> {
     pub v: f64,
 
     pub front_face: bool,
+
+    // factor the path picks up for how this hit was sampled, media with per channel free flights need it, one for surfaces
+    pub weight: Color,
     
     // explaination: the reference counted smart pointer, Rc<T> type keeps track of the number of references to a value to determine whether or not the value is still in use
     // pub material: Rc<dyn Material>
@@ -23,11 +30,73 @@ pub struct HitRecord<'a> {
     pub material: &'a dyn Material
 }
 
+// a point on the surface of a light, pdf is per unit area and includes the choice among several lights
+pub struct SurfaceSample {
+    pub position: Point3,
+    pub normal: Vec3,
+    pub pdf: f64
+}
+
+// a ray leaving a light, used to start paths from the lights
+pub struct EmittedRay<'a> {
+    pub ray: Ray,
+    // the light seen from the side the ray leaves
+    pub rec: HitRecord<'a>,
+    pub radiance: Color,
+    pub pdf_position: f64,
+    pub pdf_direction: f64
+}
+
 pub trait Hittable: Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
+    // fraction of light getting through between t_min and t_max per channel, surfaces block all of it and media some
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        if self.hit(r, t_min, t_max).is_some() { Color::new(0.0, 0.0, 0.0) } else { Color::new(1.0, 1.0, 1.0) }
+    }
     fn pdf_value(&self, o: Point3, v: Vec3) -> f64 { 0.0 }
     fn random(&self, o: Vec3) -> Vec3 { Vec3::new(1.0, 0.0, 0.0) }
+    // estimated emitted power, zero for objects that do not emit
+    fn power(&self) -> f64 { 0.0 }
+    // bounds used by the light BVH, emitting in every direction unless overridden
+    fn light_bounds(&self) -> Option<LightBounds> {
+        self.bounding_box(0.0, 1.0).map(|bbox| LightBounds::omnidirectional(bbox, self.power()))
+    }
+    // uniform point on the surface, lists pick a light proportional to its power
+    fn sample_surface(&self, _time: f64) -> Option<SurfaceSample> { None }
+    // cosine weighted ray leaving the emitting side of a sampled surface point
+    fn sample_emitted_ray(&self, time: f64) -> Option<EmittedRay<'_>> {
+        let surface = self.sample_surface(time)?;
+        // look at the point along its normal to find out which side emits
+        let probe = Ray::new(surface.position + surface.normal, (-1.0) * surface.normal, time);
+        let rec = self.hit(&probe, 0.999, 1.001)?;
+        let (normal, side_pdf) = if rec.material.emits_both_sides() {
+            let side = if rand::thread_rng().gen::<bool>() { 1.0 } else { -1.0 };
+            (side * surface.normal, 0.5)
+        } else if rec.front_face {
+            (surface.normal, 1.0)
+        } else {
+            ((-1.0) * surface.normal, 1.0)
+        };
+
+        let pdf = PDF::cosine_pdf(normal);
+        let direction = pdf.generate()?.normalized();
+        let pdf_direction = side_pdf * pdf.value(direction);
+        if pdf_direction <= 0.0 {
+            return None
+        }
+
+        let incoming = Ray::new(surface.position + direction, (-1.0) * direction, time);
+        let rec = self.hit(&incoming, 0.999, 1.001)?;
+        let radiance = rec.material.emitted(&incoming, &rec);
+        Some(EmittedRay {
+            ray: Ray::new(surface.position, direction, time),
+            rec,
+            radiance,
+            pdf_position: surface.pdf,
+            pdf_direction
+        })
+    }
 }
 
 impl HitRecord<'_> {
@@ -46,12 +115,28 @@ impl HitRecord<'_> {
 
 #[derive(Default)]
 pub struct HittableList {
-    pub list: Vec<Box<dyn Hittable>>
+    pub list: Vec<Box<dyn Hittable>>,
+    // chooses the members proportional to their estimated emitted power, built on the first light sample with the
+    // total power, lists that do not emit choose uniformly
+    table: OnceLock<(AliasTable, f64)>
 }
 
 impl HittableList {
     pub fn push(&mut self, hittable: impl Hittable + 'static) {
-        self.list.push(Box::new(hittable))
+        self.list.push(Box::new(hittable));
+        self.table = OnceLock::new();
+    }
+
+    fn table(&self) -> &(AliasTable, f64) {
+        self.table.get_or_init(|| {
+            // members without bounds (environments) can not estimate their power, they share half of the samples like in LightBVH
+            let bounded_power: f64 = self.list.iter().filter(|h| h.light_bounds().is_some()).map(|h| h.power()).sum();
+            let infinite_power = if bounded_power > 0.0 { bounded_power } else { 1.0 };
+            let powers: Vec<f64> = self.list.iter()
+                .map(|h| if h.light_bounds().is_some() { h.power() } else { infinite_power })
+                .collect();
+            (AliasTable::new(&powers), self.power())
+        })
     }
 }
 
@@ -70,6 +155,17 @@ impl Hittable for HittableList {
         temp_rec
     }
 
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        let mut transmittance = Color::new(1.0, 1.0, 1.0);
+        for object in &self.list {
+            transmittance = transmittance * object.transmittance(r, t_min, t_max);
+            if transmittance.near_zero() {
+                break
+            }
+        }
+        transmittance
+    }
+
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         match self.list.first() {
             Some(first) =>
@@ -88,11 +184,45 @@ impl Hittable for HittableList {
     }
 
     fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
-        self.list.iter().map(|h| h.pdf_value(o, v)).sum::<f64>() / self.list.len() as f64
+        if self.list.is_empty() {
+            // an empty light list samples the sphere of directions uniformly
+            return 1.0 / (4.0 * std::f64::consts::PI)
+        }
+        let (table, _) = self.table();
+        self.list.iter().enumerate().map(|(i, h)| table.pmf(i) * h.pdf_value(o, v)).sum()
     }
 
     fn random(&self, o: Vec3) -> Vec3 {
-        self.list.choose(&mut rand::thread_rng()).unwrap().random(o)
+        if self.list.is_empty() {
+            return Vec3::random_in_unit_sphere().normalized()
+        }
+        let (index, _) = self.table().0.sample(rand::thread_rng().gen::<f64>());
+        self.list[index].random(o)
+    }
+
+    fn power(&self) -> f64 {
+        self.list.iter().map(|h| h.power()).sum()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        let (table, power) = self.table();
+        if *power <= 0.0 {
+            return None
+        }
+        let (index, pmf) = table.sample(rand::thread_rng().gen::<f64>());
+        self.list[index].sample_surface(time).map(|mut sample| {
+            sample.pdf *= pmf;
+            sample
+        })
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        self.list.iter().try_fold(None, |acc: Option<LightBounds>, h|
+            h.light_bounds().map(|b| Some(match acc {
+                Some(acc) => acc.union(&b),
+                None => b
+            }))
+        ).flatten()
     }
 }
 
@@ -130,4 +260,50 @@ impl<H: Hittable> Hittable for FlipNormal<H> {
     fn random(&self, o: Vec3) -> Vec3 {
         self.hittable.random(o)
     }
-}
\ No newline at end of file
+
+    fn power(&self) -> f64 {
+        self.hittable.power()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        self.hittable.light_bounds().map(|mut bounds| {
+            bounds.axis = (-1.0) * bounds.axis;
+            bounds
+        })
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        self.hittable.sample_surface(time)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::mat::DiffuseLight;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn lists_choose_lights_by_power() {
+        let mut lights = HittableList::default();
+        lights.push(Sphere::new(Point3::new(-10.0, 0.0, 0.0), 1.0, DiffuseLight::new(ConstantTexture::new(Color::new(4.0, 4.0, 4.0)))));
+        lights.push(Sphere::new(Point3::new(10.0, 0.0, 0.0), 1.0, DiffuseLight::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0)))));
+
+        let o = Point3::new(0.0, 0.0, 0.0);
+        const SAMPLES: usize = 100000;
+        let bright = (0..SAMPLES).filter(|_| lights.random(o).x() < 0.0).count() as f64 / SAMPLES as f64;
+        assert!((bright - 0.8).abs() < 0.01);
+
+        // the pdf of a direction is the chance of its light times that light's own pdf
+        let towards = |x: f64| Vec3::new(x, 0.0, 0.0);
+        assert!((lights.pdf_value(o, towards(-1.0)) - 0.8 * lights.list[0].pdf_value(o, towards(-1.0))).abs() < 1e-12);
+        assert!((lights.pdf_value(o, towards(1.0)) - 0.2 * lights.list[1].pdf_value(o, towards(1.0))).abs() < 1e-12);
+
+        let area = 4.0 * std::f64::consts::PI;
+        for _ in 0..100 {
+            let sample = lights.sample_surface(0.0).unwrap();
+            let chance = if sample.position.x() < 0.0 { 0.8 } else { 0.2 };
+            assert!((sample.pdf - chance / area).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::f64;
+use std::fs;
+use std::sync::Arc;
+use super::vec::Vec3;
+use super::onb::ONB;
+
+struct Photometry {
+    // degrees, vertical angles start at the nadir
+    vertical_angles: Vec<f64>,
+    horizontal_angles: Vec<f64>,
+    // one row of vertical samples per horizontal angle, normalized so the peak is 1
+    candela: Vec<Vec<f64>>,
+    // integral of the normalized profile over the sphere
+    integral: f64,
+    // integral over the hemisphere around the nadir over pi, what a lambertian emitter of the same peak would give as one
+    hemisphere_average: f64
+}
+
+// LM-63 photometric profile (type C), describes how a fixture distributes light over directions
+#[derive(Clone)]
+pub struct IesProfile {
+    photometry: Arc<Photometry>
+}
+
+// linear interpolation of a tabulated function at x, zero outside of the table
+fn interpolate(xs: &[f64], x: f64) -> Option<(usize, f64)> {
+    if xs.len() == 1 {
+        return Some((0, 0.0))
+    }
+    if x < xs[0] || x > xs[xs.len() - 1] {
+        return None
+    }
+    let i = xs.partition_point(|&a| a <= x).clamp(1, xs.len() - 1) - 1;
+    let t = if xs[i + 1] > xs[i] { (x - xs[i]) / (xs[i + 1] - xs[i]) } else { 0.0 };
+    Some((i, t))
+}
+
+impl IesProfile {
+    pub fn load(path: &str) -> IesProfile {
+        let text = fs::read_to_string(path).expect("IES file not found");
+        IesProfile::parse(&text).unwrap_or_else(|error| panic!("{}: {}", path, error))
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, String> {
+        let mut lines = text.lines();
+        // skip the header and keywords up to the tilt line
+        let tilt = lines.by_ref().find(|line| line.trim_start().starts_with("TILT=")).ok_or("missing TILT line")?;
+        let mut numbers = lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ',')).filter(|s| !s.is_empty()).map(|s| s.parse::<f64>());
+        let mut next = || numbers.next().ok_or("unexpected end of file".to_string())?.map_err(|error| error.to_string());
+
+        if tilt.trim() == "TILT=INCLUDE" {
+            // lamp to luminaire geometry, then the tilt angles and their multipliers
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as usize;
+        if photometric_type != 1 {
+            return Err(format!("photometric type {} is not supported, only type C", photometric_type))
+        }
+        // units, width, length, height, ballast factor, future use and input watts
+        for _ in 0..7 {
+            next()?;
+        }
+
+        let vertical_angles = (0..vertical_count).map(|_| next()).collect::<Result<Vec<f64>, String>>()?;
+        let horizontal_angles = (0..horizontal_count).map(|_| next()).collect::<Result<Vec<f64>, String>>()?;
+        let mut candela = (0..horizontal_count)
+            .map(|_| (0..vertical_count).map(|_| next().map(|c| c * multiplier)).collect::<Result<Vec<f64>, String>>())
+            .collect::<Result<Vec<Vec<f64>>, String>>()?;
+
+        let peak = candela.iter().flatten().fold(0.0, |a: f64, &b| a.max(b));
+        if peak <= 0.0 {
+            return Err("profile emits no light".to_string())
+        }
+        for c in candela.iter_mut().flatten() {
+            *c /= peak;
+        }
+
+        let mut photometry = Photometry {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            integral: 0.0,
+            hemisphere_average: 0.0
+        };
+
+        // midpoint rule on a grid over the sphere
+        let (nt, np) = (90, 180);
+        let d_theta = f64::consts::PI / nt as f64;
+        let d_phi = 2.0 * f64::consts::PI / np as f64;
+        for i in 0..nt {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..np {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let value = photometry.evaluate(theta.to_degrees(), phi.to_degrees()) * theta.sin() * d_theta * d_phi;
+                photometry.integral += value;
+                if theta < 0.5 * f64::consts::PI {
+                    photometry.hemisphere_average += value / f64::consts::PI;
+                }
+            }
+        }
+
+        Ok(IesProfile {
+            photometry: Arc::new(photometry)
+        })
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.photometry.integral
+    }
+
+    pub fn hemisphere_average(&self) -> f64 {
+        self.photometry.hemisphere_average
+    }
+
+    // normalized intensity toward a direction, the frame's w axis is the nadir of the fixture
+    pub fn value(&self, frame: &ONB, direction: Vec3) -> f64 {
+        let d = direction.normalized();
+        let cos_theta = d.dot(frame.w()).clamp(-1.0, 1.0);
+        let phi = d.dot(frame.v()).atan2(d.dot(frame.u())).to_degrees().rem_euclid(360.0);
+        self.photometry.evaluate(cos_theta.acos().to_degrees(), phi)
+    }
+}
+
+impl Photometry {
+    fn evaluate(&self, vertical: f64, horizontal: f64) -> f64 {
+        // fold the horizontal angle according to the symmetry of the file
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        let horizontal = if last <= 90.0 {
+            let h = if horizontal > 180.0 { 360.0 - horizontal } else { horizontal };
+            if h > 90.0 { 180.0 - h } else { h }
+        } else if last <= 180.0 && horizontal > 180.0 {
+            360.0 - horizontal
+        } else {
+            horizontal
+        };
+
+        let (v, tv) = match interpolate(&self.vertical_angles, vertical) {
+            Some(v) => v,
+            None => return 0.0
+        };
+        let (h, th) = interpolate(&self.horizontal_angles, horizontal).unwrap_or((self.horizontal_angles.len() - 1, 0.0));
+
+        let sample = |h: usize, v: usize| {
+            let h = h.min(self.candela.len() - 1);
+            let v = v.min(self.candela[h].len() - 1);
+            self.candela[h][v]
+        };
+        let lerp = |h: usize| (1.0 - tv) * sample(h, v) + tv * sample(h, v + 1);
+        (1.0 - th) * lerp(h) + th * lerp(h + 1)
+    }
+}
@@ -0,0 +1,106 @@
+use super::vec::Vec3;
+use super::ray::Ray;
+use super::hit::{Hittable, HitRecord};
+use super::mat::ScatterRecord;
+use super::medium::Medium;
+
+// the closed volume behind a dielectric surface
+#[derive(Clone, Copy)]
+pub struct Interior<'a> {
+    pub ior: f64,
+    // where volumes overlap the one with the highest priority counts, among equals the one entered last
+    pub priority: u32,
+    pub medium: Option<&'a dyn Medium>
+}
+
+impl Interior<'_> {
+    // meshes copy their material into every triangle, so volumes are told apart by what they are made of
+    fn same(&self, other: &Interior) -> bool {
+        self.ior == other.ior && self.priority == other.priority && match (self.medium, other.medium) {
+            (Some(a), Some(b)) => std::ptr::addr_eq(a, b),
+            (None, None) => true,
+            _ => false
+        }
+    }
+}
+
+// the volumes a path is inside, paths start outside of all of them
+#[derive(Default)]
+pub struct InteriorStack<'a> {
+    interiors: Vec<Interior<'a>>
+}
+
+impl<'a> InteriorStack<'a> {
+    fn current(&self) -> Option<usize> {
+        (0..self.interiors.len()).max_by_key(|&i| self.interiors[i].priority)
+    }
+
+    fn find(&self, interior: &Interior) -> Option<usize> {
+        self.interiors.iter().rposition(|other| other.same(interior))
+    }
+
+    fn ior(&self) -> f64 {
+        self.current().map_or(1.0, |i| self.interiors[i].ior)
+    }
+
+    // the medium the path is travelling through
+    pub fn medium(&self) -> Option<&'a dyn Medium> {
+        self.current().and_then(|i| self.interiors[i].medium)
+    }
+
+    // the first surface along ray, or a scattering in the medium before it
+    pub fn hit(&self, world: &'a dyn Hittable, ray: &Ray) -> Option<HitRecord<'a>> {
+        let rec = world.hit(ray, 0.00001, f64::INFINITY)?;
+        match self.medium() {
+            Some(medium) => medium.sample(ray, 0.00001, rec.t).or(Some(rec)),
+            None => Some(rec)
+        }
+    }
+
+    // scattering at rec with the indices of refraction on both sides, surfaces of a volume overridden by a higher
+    // priority one are crossed as if they were not there
+    pub fn scatter(&mut self, r_in: &Ray, rec: &HitRecord<'a>) -> Option<ScatterRecord<'a>> {
+        let interior = match rec.material.interior() {
+            Some(interior) => interior,
+            None => return rec.material.scatter_mc_method(r_in, rec)
+        };
+        let pass_through = Some(ScatterRecord::PassThrough { ray: Ray::new(rec.position, r_in.direction(), r_in.time()) });
+
+        let eta = if rec.front_face {
+            if self.current().is_some_and(|i| self.interiors[i].priority > interior.priority) {
+                self.interiors.push(interior);
+                return pass_through
+            }
+            self.ior() / interior.ior
+        } else {
+            match self.find(&interior) {
+                Some(index) if Some(index) != self.current() => {
+                    self.interiors.remove(index);
+                    return pass_through
+                }
+                Some(index) => {
+                    let entry = self.interiors.remove(index);
+                    let outside = self.ior();
+                    self.interiors.insert(index, entry);
+                    interior.ior / outside
+                }
+                // left without having been entered, like a camera placed inside
+                None => interior.ior / self.ior()
+            }
+        };
+        rec.material.scatter_interface(r_in, rec, eta)
+    }
+
+    // the path goes on from rec along direction, going through the surface enters or leaves its volume
+    pub fn cross(&mut self, rec: &HitRecord<'a>, direction: Vec3) {
+        let interior = match rec.material.interior() {
+            Some(interior) if direction.dot(rec.normal) < 0.0 => interior,
+            _ => return
+        };
+        if rec.front_face {
+            self.interiors.push(interior);
+        } else if let Some(index) = self.find(&interior) {
+            self.interiors.remove(index);
+        }
+    }
+}
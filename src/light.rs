@@ -0,0 +1,290 @@
+use std::f64;
+use rand::Rng;
+use super::vec::{Vec3, Point3, Color};
+use super::ray::Ray;
+use super::hit::Hittable;
+use super::aabb::AABB;
+use super::distribution::AliasTable;
+use super::onb::ONB;
+use super::ies::IesProfile;
+
+// the area lights as one emitter followed by the delta lights, chosen proportional to power, for paths that start at the lights
+pub struct LightEmitter<'a> {
+    lights: &'a dyn Hittable,
+    delta_lights: &'a [Box<dyn DeltaLight>],
+    // bounds of the whole scene, directional lights shoot through a disk covering them
+    scene: AABB,
+    table: Option<AliasTable>
+}
+
+impl<'a> LightEmitter<'a> {
+    pub fn new(lights: &'a dyn Hittable, delta_lights: &'a [Box<dyn DeltaLight>], world: &dyn Hittable) -> LightEmitter<'a> {
+        // an empty world has no bounds, and nothing for directional lights to shine on
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let scene = world.bounding_box(0.0, 1.0).unwrap_or(AABB::new(origin, origin));
+        // environment lights can not start paths, they only light the scene directly
+        let powers: Vec<f64> = std::iter::once(lights.power()).chain(delta_lights.iter().map(|light| light.power(&scene))).collect();
+        let table = if powers.iter().sum::<f64>() > 0.0 { Some(AliasTable::new(&powers)) } else { None };
+        LightEmitter {
+            lights,
+            delta_lights,
+            scene,
+            table
+        }
+    }
+
+    pub fn sample(&self, time: f64) -> Option<EmitterSample> {
+        let table = self.table.as_ref()?;
+        let (index, pmf) = table.sample(rand::thread_rng().gen::<f64>());
+        if index == 0 {
+            let emitted = self.lights.sample_emitted_ray(time)?;
+            let cosine = emitted.rec.normal.dot(emitted.ray.direction()).abs();
+            Some(EmitterSample {
+                ray: emitted.ray,
+                power: emitted.radiance * cosine / (pmf * emitted.pdf_position * emitted.pdf_direction),
+                pdf_position: Some(pmf * emitted.pdf_position)
+            })
+        } else {
+            let (ray, power) = self.delta_lights[index - 1].sample_le(time, &self.scene)?;
+            Some(EmitterSample {
+                ray,
+                power: power / pmf,
+                pdf_position: None
+            })
+        }
+    }
+}
+
+// a ray leaving a light and the power it carries over its density
+pub struct EmitterSample {
+    pub ray: Ray,
+    pub power: Color,
+    // area density of the starting point, none for delta lights
+    pub pdf_position: Option<f64>
+}
+
+// direction toward the light, distance to it and the irradiance it delivers to a surface facing it
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f64,
+    pub irradiance: Color
+}
+
+// lights that can not be hit by rays, only reached through explicit light sampling
+pub trait DeltaLight: Sync {
+    fn sample_li(&self, p: Point3) -> Option<LightSample>;
+    // a ray leaving the light and the power it carries over its density, for tracing photons. the bounds
+    // of the scene tell lights outside of it where to aim
+    fn sample_le(&self, _time: f64, _scene: &AABB) -> Option<(Ray, Color)> { None }
+    // emitted power as luminance, to share photons between lights
+    fn power(&self, _scene: &AABB) -> f64 { 0.0 }
+}
+
+#[derive(Clone)]
+pub struct PointLight {
+    position: Point3,
+    // radiant intensity in W/sr
+    intensity: Color,
+    profile: Option<(IesProfile, ONB)>
+}
+
+impl PointLight {
+    // power in watts, spread evenly over the sphere
+    pub fn new(position: Point3, color: Color, power: f64) -> PointLight {
+        PointLight {
+            position,
+            intensity: color * power / (4.0 * f64::consts::PI),
+            profile: None
+        }
+    }
+
+    // distribute the power following a photometric profile, the fixture points down
+    pub fn with_profile(self, profile: IesProfile) -> PointLight {
+        PointLight {
+            intensity: self.intensity * 4.0 * f64::consts::PI / profile.integral(),
+            profile: Some((profile, ONB::build_from_w(&Vec3::new(0.0, -1.0, 0.0)))),
+            ..self
+        }
+    }
+}
+
+impl DeltaLight for PointLight {
+    fn sample_li(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let profile = self.profile.as_ref().map_or(1.0, |(profile, frame)| profile.value(frame, (-1.0) * direction));
+        if profile == 0.0 {
+            return None
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            irradiance: self.intensity * profile / distance.powi(2)
+        })
+    }
+
+    fn sample_le(&self, time: f64, _scene: &AABB) -> Option<(Ray, Color)> {
+        let direction = Vec3::random_in_unit_sphere().normalized();
+        let profile = self.profile.as_ref().map_or(1.0, |(profile, frame)| profile.value(frame, direction));
+        if profile == 0.0 {
+            return None
+        }
+        Some((Ray::new(self.position, direction, time), self.intensity * profile * 4.0 * f64::consts::PI))
+    }
+
+    fn power(&self, _scene: &AABB) -> f64 {
+        self.intensity.luminance() * self.profile.as_ref().map_or(4.0 * f64::consts::PI, |(profile, _)| profile.integral())
+    }
+}
+
+#[derive(Clone)]
+pub struct SpotLight {
+    position: Point3,
+    axis: Vec3,
+    intensity: Color,
+    cos_falloff_start: f64,
+    cos_falloff_end: f64,
+    profile: Option<(IesProfile, ONB)>
+}
+
+impl SpotLight {
+    // full intensity inside falloff_start degrees, fading to zero at total_width degrees
+    pub fn new(position: Point3, look_at: Point3, color: Color, power: f64, falloff_start: f64, total_width: f64) -> SpotLight {
+        let cos_falloff_start = falloff_start.to_radians().cos();
+        let cos_falloff_end = total_width.to_radians().cos();
+        // solid angle of the cone with the smoothstep falloff approximated as linear
+        let k = 2.0 * f64::consts::PI * ((1.0 - cos_falloff_start) + 0.5 * (cos_falloff_start - cos_falloff_end));
+        SpotLight {
+            position,
+            axis: (look_at - position).normalized(),
+            intensity: color * power / k,
+            cos_falloff_start,
+            cos_falloff_end,
+            profile: None
+        }
+    }
+
+    // shape the beam inside the cone with a photometric profile whose nadir is the spot axis
+    pub fn with_profile(self, profile: IesProfile) -> SpotLight {
+        SpotLight {
+            profile: Some((profile, ONB::build_from_w(&self.axis))),
+            ..self
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else if cos_theta <= self.cos_falloff_end {
+            0.0
+        } else {
+            let t = (cos_theta - self.cos_falloff_end) / (self.cos_falloff_start - self.cos_falloff_end);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl DeltaLight for SpotLight {
+    fn sample_li(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let falloff = self.falloff(((-1.0) * direction).dot(self.axis))
+            * self.profile.as_ref().map_or(1.0, |(profile, frame)| profile.value(frame, (-1.0) * direction));
+        if falloff == 0.0 {
+            return None
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            irradiance: self.intensity * falloff / distance.powi(2)
+        })
+    }
+
+    fn sample_le(&self, time: f64, _scene: &AABB) -> Option<(Ray, Color)> {
+        // uniform direction inside the outer cone
+        let mut rng = rand::thread_rng();
+        let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - self.cos_falloff_end);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f64::consts::PI * rng.gen::<f64>();
+        let direction = ONB::build_from_w(&self.axis).local(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta));
+        let falloff = self.falloff(cos_theta) * self.profile.as_ref().map_or(1.0, |(profile, frame)| profile.value(frame, direction));
+        if falloff == 0.0 {
+            return None
+        }
+        let solid_angle = 2.0 * f64::consts::PI * (1.0 - self.cos_falloff_end);
+        Some((Ray::new(self.position, direction, time), self.intensity * falloff * solid_angle))
+    }
+
+    fn power(&self, _scene: &AABB) -> f64 {
+        // the profile only reshapes the beam, photons are still shared well enough without it
+        let k = 2.0 * f64::consts::PI * ((1.0 - self.cos_falloff_start) + 0.5 * (self.cos_falloff_start - self.cos_falloff_end));
+        self.intensity.luminance() * k
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DirectionalLight {
+    // unit vector pointing toward the light
+    to_light: Vec3,
+    // irradiance in W/m^2 on a surface facing the light
+    irradiance: Color,
+    cos_theta_max: f64
+}
+
+impl DirectionalLight {
+    // a non-zero angular diameter in degrees gives a disk like the sun (about 0.53) and soft shadows
+    pub fn new(direction: Vec3, color: Color, irradiance: f64, angular_diameter: f64) -> DirectionalLight {
+        DirectionalLight {
+            to_light: (-1.0) * direction.normalized(),
+            irradiance: color * irradiance,
+            cos_theta_max: (0.5 * angular_diameter).to_radians().cos()
+        }
+    }
+}
+
+impl DirectionalLight {
+    fn sample_direction(&self) -> Vec3 {
+        if self.cos_theta_max < 1.0 {
+            // uniform direction inside the cone, radiance over pdf gives back the irradiance
+            let mut rng = rand::thread_rng();
+            let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - self.cos_theta_max);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * f64::consts::PI * rng.gen::<f64>();
+            ONB::build_from_w(&self.to_light).local(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta))
+        } else {
+            self.to_light
+        }
+    }
+}
+
+// center and radius of a sphere around the scene
+fn bounding_sphere(scene: &AABB) -> (Point3, f64) {
+    (0.5 * (scene.min + scene.max), 0.5 * (scene.max - scene.min).length())
+}
+
+impl DeltaLight for DirectionalLight {
+    fn sample_li(&self, _p: Point3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.sample_direction(),
+            distance: f64::INFINITY,
+            irradiance: self.irradiance
+        })
+    }
+
+    fn sample_le(&self, time: f64, scene: &AABB) -> Option<(Ray, Color)> {
+        // the rays start on a disk facing the light that covers the scene, outside of its bounding sphere
+        let (center, radius) = bounding_sphere(scene);
+        let to_light = self.sample_direction();
+        let frame = ONB::build_from_w(&to_light);
+        let disk = Vec3::random_in_unit_disk();
+        let origin = center + radius * frame.local(&Vec3::new(disk.x(), disk.y(), 1.0));
+        Some((Ray::new(origin, (-1.0) * to_light, time), self.irradiance * f64::consts::PI * radius * radius))
+    }
+
+    fn power(&self, scene: &AABB) -> f64 {
+        let (_, radius) = bounding_sphere(scene);
+        self.irradiance.luminance() * f64::consts::PI * radius * radius
+    }
+}
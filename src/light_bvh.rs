@@ -0,0 +1,314 @@
+use std::f64;
+use rand::Rng;
+use super::vec::{Vec3, Point3};
+use super::ray::Ray;
+use super::hit::{Hittable, HitRecord, HittableList, SurfaceSample};
+use super::aabb;
+use super::aabb::AABB;
+
+// rotate v around a unit axis by theta (Rodrigues' formula)
+fn rotate_around(v: Vec3, axis: Vec3, theta: f64) -> Vec3 {
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    cos_theta * v + sin_theta * axis.cross(v) + (1.0 - cos_theta) * axis.dot(v) * axis
+}
+
+// cos(max(0, a - b)) given the cosines of both angles
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+fn sin_from_cos(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta * cos_theta).max(0.0).sqrt()
+}
+
+// spatial and directional bounds of the emission of a light or a group of lights
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub bbox: AABB,
+    pub power: f64,
+    // emission happens around axis within theta_o, falling off to zero at theta_o + theta_e
+    pub axis: Vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64
+}
+
+impl LightBounds {
+    pub fn new(bbox: AABB, power: f64, axis: Vec3, cos_theta_o: f64, cos_theta_e: f64) -> LightBounds {
+        LightBounds {
+            bbox,
+            power,
+            axis,
+            cos_theta_o,
+            cos_theta_e
+        }
+    }
+
+    // a light emitting in every direction
+    pub fn omnidirectional(bbox: AABB, power: f64) -> LightBounds {
+        LightBounds::new(bbox, power, Vec3::new(0.0, 0.0, 1.0), -1.0, 0.0)
+    }
+
+    // a one-sided planar emitter
+    pub fn one_sided(bbox: AABB, power: f64, normal: Vec3) -> LightBounds {
+        LightBounds::new(bbox, power, normal.normalized(), 1.0, 0.0)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.bbox.min + self.bbox.max)
+    }
+
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0.0 {
+            return *other
+        }
+        if other.power == 0.0 {
+            return *self
+        }
+
+        let theta_a = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_b = other.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_d = self.axis.dot(other.axis).clamp(-1.0, 1.0).acos();
+
+        let (axis, theta_o) = if f64::min(theta_d + theta_b, f64::consts::PI) <= theta_a {
+            (self.axis, theta_a)
+        } else if f64::min(theta_d + theta_a, f64::consts::PI) <= theta_b {
+            (other.axis, theta_b)
+        } else {
+            // smallest cone enclosing both
+            let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+            let wr = self.axis.cross(other.axis);
+            if theta_o >= f64::consts::PI || wr.near_zero() {
+                (self.axis, f64::consts::PI)
+            } else {
+                (rotate_around(self.axis, wr.normalized(), theta_o - theta_a), theta_o)
+            }
+        };
+
+        LightBounds {
+            bbox: aabb::surrounding_box(&self.bbox, &other.bbox),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o: theta_o.cos(),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e)
+        }
+    }
+
+    // conservative estimate of the light reaching p, based on distance, power and orientation
+    pub fn importance(&self, p: Point3) -> f64 {
+        let pc = self.centroid();
+        let diagonal = self.bbox.max - self.bbox.min;
+        let distance_squared = (p - pc).length().powi(2).max(0.5 * diagonal.length());
+
+        let wi = p - pc;
+        if wi.near_zero() {
+            return self.power / distance_squared
+        }
+        let cos_theta_w = wi.normalized().dot(self.axis);
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+
+        // angle subtended by the bounding sphere of the box as seen from p
+        let radius_squared = (0.5 * diagonal.length()).powi(2);
+        let center_distance_squared = wi.length().powi(2);
+        let cos_theta_b = if center_distance_squared < radius_squared {
+            -1.0
+        } else {
+            (1.0 - radius_squared / center_distance_squared).max(0.0).sqrt()
+        };
+        let sin_theta_b = sin_from_cos(cos_theta_b);
+
+        // minimum angle between the emission cone and the direction to p
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_from_cos(cos_theta_x);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0
+        }
+
+        self.power * cos_theta_p / distance_squared
+    }
+}
+
+struct LightNode {
+    bounds: LightBounds,
+    tree: LightTree
+}
+
+enum LightTree {
+    Branch { left: Box<LightNode>, right: Box<LightNode> },
+    Leaf(usize)
+}
+
+// probability of descending into each child for a shading point p
+fn child_probabilities(p: Point3, left: &LightNode, right: &LightNode) -> (f64, f64) {
+    let il = left.bounds.importance(p);
+    let ir = right.bounds.importance(p);
+    if il + ir == 0.0 {
+        (0.5, 0.5)
+    } else {
+        (il / (il + ir), ir / (il + ir))
+    }
+}
+
+impl LightNode {
+    fn build(mut lights: Vec<(usize, LightBounds)>) -> LightNode {
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0];
+            return LightNode { bounds, tree: LightTree::Leaf(index) }
+        }
+
+        // split at the median along the widest extent of the centroids
+        let (min, max) = lights.iter().fold((Vec3::new(f64::MAX, f64::MAX, f64::MAX), Vec3::new(f64::MIN, f64::MIN, f64::MIN)), |(min, max), (_, b)| {
+            let c = b.centroid();
+            (Vec3::new(min.x().min(c.x()), min.y().min(c.y()), min.z().min(c.z())),
+             Vec3::new(max.x().max(c.x()), max.y().max(c.y()), max.z().max(c.z())))
+        });
+        let extent = max - min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() { 0 } else if extent.y() > extent.z() { 1 } else { 2 };
+        lights.sort_unstable_by(|a, b| a.1.centroid()[axis].partial_cmp(&b.1.centroid()[axis]).unwrap());
+
+        let length = lights.len();
+        let right = LightNode::build(lights.split_off(length / 2));
+        let left = LightNode::build(lights);
+        LightNode {
+            bounds: left.bounds.union(&right.bounds),
+            tree: LightTree::Branch { left: Box::new(left), right: Box::new(right) }
+        }
+    }
+}
+
+// many-light sampler, picks one light per shading point proportional to its estimated contribution
+pub struct LightBVH {
+    lights: HittableList,
+    // lights without bounds, e.g. environment lights
+    infinite: Vec<usize>,
+    root: Option<LightNode>
+}
+
+impl LightBVH {
+    pub fn new(lights: HittableList) -> LightBVH {
+        let mut infinite = Vec::new();
+        let mut bounded = Vec::new();
+        for (i, light) in lights.list.iter().enumerate() {
+            match light.light_bounds() {
+                Some(bounds) => if bounds.power > 0.0 {
+                    bounded.push((i, bounds))
+                },
+                None => infinite.push(i)
+            }
+        }
+
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(LightNode::build(bounded))
+        };
+
+        LightBVH {
+            lights,
+            infinite,
+            root
+        }
+    }
+
+    fn infinite_probability(&self) -> f64 {
+        let bvh = if self.root.is_some() { 1.0 } else { 0.0 };
+        if self.infinite.is_empty() {
+            0.0
+        } else {
+            self.infinite.len() as f64 / (self.infinite.len() as f64 + bvh)
+        }
+    }
+
+    fn pdf_node(&self, node: &LightNode, ray: &Ray, prob: f64) -> f64 {
+        // only lights whose bounds are crossed by the direction can be hit
+        if prob == 0.0 || !node.bounds.bbox.hit(ray, 0.0, f64::INFINITY) {
+            return 0.0
+        }
+        match &node.tree {
+            LightTree::Leaf(index) => prob * self.lights.list[*index].pdf_value(ray.origin(), ray.direction()),
+            LightTree::Branch { left, right } => {
+                let (pl, pr) = child_probabilities(ray.origin(), left, right);
+                self.pdf_node(left, ray, prob * pl) + self.pdf_node(right, ray, prob * pr)
+            }
+        }
+    }
+}
+
+impl Hittable for LightBVH {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.lights.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.lights.bounding_box(t0, t1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        let p_infinite = self.infinite_probability();
+        let infinite: f64 = self.infinite.iter()
+            .map(|&i| p_infinite / self.infinite.len() as f64 * self.lights.list[i].pdf_value(o, v))
+            .sum();
+
+        match &self.root {
+            Some(root) => infinite + self.pdf_node(root, &Ray::new(o, v, 0.0), 1.0 - p_infinite),
+            None => infinite
+        }
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        if rng.gen::<f64>() < self.infinite_probability() {
+            let index = self.infinite[rng.gen_range(0..self.infinite.len())];
+            return self.lights.list[index].random(o)
+        }
+
+        let mut node = match &self.root {
+            Some(root) => root,
+            None => return Vec3::new(1.0, 0.0, 0.0)
+        };
+        loop {
+            match &node.tree {
+                LightTree::Leaf(index) => return self.lights.list[*index].random(o),
+                LightTree::Branch { left, right } => {
+                    let (pl, _) = child_probabilities(o, left, right);
+                    node = if rng.gen::<f64>() < pl { left } else { right };
+                }
+            }
+        }
+    }
+
+    fn power(&self) -> f64 {
+        self.lights.power()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        // without a shading point the tree is descended by power alone, infinite lights have no surface
+        let mut rng = rand::thread_rng();
+        let mut node = self.root.as_ref()?;
+        let mut pdf = 1.0;
+        loop {
+            match &node.tree {
+                LightTree::Leaf(index) => return self.lights.list[*index].sample_surface(time).map(|mut sample| {
+                    sample.pdf *= pdf;
+                    sample
+                }),
+                LightTree::Branch { left, right } => {
+                    let total = left.bounds.power + right.bounds.power;
+                    let pl = if total > 0.0 { left.bounds.power / total } else { 0.5 };
+                    if rng.gen::<f64>() < pl {
+                        pdf *= pl;
+                        node = left;
+                    } else {
+                        pdf *= 1.0 - pl;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+}
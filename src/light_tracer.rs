@@ -0,0 +1,155 @@
+use std::f64;
+use std::io::{stderr, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rand::Rng;
+use rayon::prelude::*;
+use super::vec::{Point3, Color};
+use super::ray::Ray;
+use super::hit::HitRecord;
+use super::mat::ScatterRecord;
+use super::camera::Camera;
+use super::light::LightEmitter;
+use super::interior::InteriorStack;
+use super::film::Film;
+use super::SceneRefs;
+
+// paths start at the lights and every vertex is connected to the lens, the image only holds splats
+pub struct LightTracer<'a> {
+    camera: &'a Camera,
+    scene: SceneRefs<'a>,
+    // longest light path in bounces
+    max_depth: usize,
+    emitter: LightEmitter<'a>
+}
+
+impl<'a> LightTracer<'a> {
+    pub fn new(camera: &'a Camera, scene: SceneRefs<'a>, max_depth: usize) -> LightTracer<'a> {
+        LightTracer {
+            camera,
+            scene,
+            max_depth,
+            emitter: LightEmitter::new(scene.lights, scene.delta_lights, scene.world)
+        }
+    }
+
+    // traces as many light paths as the camera would trace samples
+    pub fn render(&self, width: usize, height: usize, samples_per_pixel: u64) -> Film {
+        let remaining = AtomicUsize::new(height);
+        (0..height).into_par_iter().fold(|| Film::new(width, height), |mut film, _| {
+            for _ in 0..width * samples_per_pixel as usize {
+                self.trace(&mut film);
+            }
+            eprint!("\rScanlines remaining: {:3}", remaining.fetch_sub(1, Ordering::Relaxed) - 1);
+            stderr().flush().unwrap();
+            film
+        })
+        .reduce(|| Film::new(width, height), Film::merge)
+    }
+
+    fn trace(&self, film: &mut Film) {
+        let mut rng = rand::thread_rng();
+        let time = self.camera.time();
+        let emitted = match self.emitter.sample(time) {
+            Some(emitted) => emitted,
+            None => return
+        };
+        // emission seen directly, delta lights can not be seen
+        if let Some(pdf_position) = emitted.pdf_position {
+            self.splat_emitter(emitted.ray.origin(), pdf_position, time, film);
+        }
+        let (mut ray, mut power) = (emitted.ray, emitted.power);
+        let mut stack = InteriorStack::default();
+        // only scattering counts towards the depth, crossing into or out of a medium is not a bounce
+        let mut depth = 0;
+
+        while depth < self.max_depth {
+            let rec = match stack.hit(self.scene.world, &ray) {
+                Some(rec) => rec,
+                None => return
+            };
+            power = power * rec.weight;
+            let srec = match stack.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => return
+            };
+
+            let next = match srec {
+                ScatterRecord::PassThrough { ray: through } => {
+                    ray = through;
+                    continue
+                }
+                ScatterRecord::Specular { specular_ray, attenuation } => Some((specular_ray, attenuation)),
+                ScatterRecord::Scatter { pdf, attenuation } | ScatterRecord::Volume { pdf, attenuation } => {
+                    let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                    self.splat(&rec, power, &f_cos, time, film);
+                    super::sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, _)| (scattered, weight))
+                }
+                ScatterRecord::Microfacet { pdf, eta } => {
+                    let f_cos = |scattered: &Ray| rec.material.bsdf(&ray, scattered, &rec, eta) * rec.normal.dot(scattered.direction()).abs();
+                    self.splat(&rec, power, &f_cos, time, film);
+                    super::sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, _)| (scattered, weight))
+                }
+            };
+
+            let (next_ray, weight) = match next {
+                Some(next) => next,
+                None => return
+            };
+            stack.cross(&rec, next_ray.direction());
+            power = power * weight;
+            if depth >= 3 {
+                // the power is absolute, so survival follows what this bounce kept
+                let survival = weight.x().max(weight.y()).max(weight.z()).min(0.95);
+                if rng.gen::<f64>() >= survival {
+                    return
+                }
+                power /= survival;
+            }
+            ray = next_ray;
+            depth += 1;
+        }
+    }
+
+    // the point of a light a path started from, as seen through the lens
+    fn splat_emitter(&self, position: Point3, pdf_position: f64, time: f64, film: &mut Film) {
+        let sample = match self.camera.sample_wi(position) {
+            Some(sample) => sample,
+            None => return
+        };
+        // looking back from the lens finds the emitting side and how much of it gets through
+        let to_light = position - sample.lens;
+        let distance = to_light.length();
+        let ray = Ray::new(sample.lens, to_light / distance, time);
+        let rec = match self.scene.lights.hit(&ray, 0.00001, f64::INFINITY) {
+            Some(rec) if (rec.t - distance).abs() <= 1e-6 * distance => rec,
+            _ => return
+        };
+        let cosine = rec.normal.dot(ray.direction()).abs();
+        let color = rec.material.emitted(&ray, &rec) * cosine * sample.importance / (pdf_position * sample.pdf);
+        if color.near_zero() {
+            return
+        }
+        let transmittance = self.scene.world.transmittance(&ray, 0.00001, distance * (1.0 - 1e-6));
+        if !transmittance.near_zero() {
+            film.splat(sample.s, sample.t, color * transmittance);
+        }
+    }
+
+    fn splat(&self, rec: &HitRecord, power: Color, f_cos: &impl Fn(&Ray) -> Color, time: f64, film: &mut Film) {
+        let sample = match self.camera.sample_wi(rec.position) {
+            Some(sample) => sample,
+            None => return
+        };
+        let to_lens = sample.lens - rec.position;
+        let distance = to_lens.length();
+        let ray = Ray::new(rec.position, to_lens / distance, time);
+        let color = power * f_cos(&ray) * sample.importance / sample.pdf;
+        if color.near_zero() {
+            return
+        }
+        let transmittance = self.scene.world.transmittance(&ray, 0.00001, distance * (1.0 - 1e-6));
+        if !transmittance.near_zero() {
+            film.splat(sample.s, sample.t, color * transmittance);
+        }
+    }
+}
@@ -8,6 +8,7 @@ mod rect;
 mod cube;
 mod tri;
 mod mesh;
+mod subdivision;
 mod camera;
 mod mat;
 mod aabb;
@@ -15,8 +16,22 @@ mod bvh;
 mod perlin;
 mod texture;
 mod medium;
+mod interior;
+mod voxel;
+mod phase;
 mod onb;
 mod pdf;
+mod distribution;
+mod light;
+mod light_bvh;
+mod environment;
+mod sky;
+mod ies;
+mod spectrum;
+mod film;
+mod bdpt;
+mod photon;
+mod light_tracer;
 
 use std::{io::{stderr, Write}};
 use rand::Rng;
@@ -25,98 +40,260 @@ use vec::{Vec3, Point3, Color};
 use ray::Ray;
 use translate::Translate;
 use rotate::{Axis, Rotate};
-use hit::{Hittable, HittableList, FlipNormal};
+use hit::{Hittable, HitRecord, HittableList, FlipNormal};
 use sphere::{Sphere, MovingSphere};
 use rect::{Plane, AARect};
 use cube::Cube;
 use tri::Triangle;
 use mesh::Mesh;
+use subdivision::Subdivision;
 use camera::Camera;
-use mat::{Lambertian, Metal, Dielectric, DiffuseLight, ScatterRecord, PBR};
+use mat::{Lambertian, Metal, Conductor, Dielectric, RoughDielectric, Coated, Mix, Blend, DiffuseLight, ScatterRecord, PBR};
 use bvh::BVH;
 use texture::{ConstantTexture, CheckTexture, NoiseTexture, ImageTexture};
-use medium::ConstantMedium;
-use pdf::PDF;
+use medium::{Medium, HomogeneousMedium, ConstantMedium, ChromaticMedium, HeterogeneousMedium, TextureDensity, EmissionField, BlackbodyEmission};
+use interior::InteriorStack;
+use voxel::VoxelGrid;
+use phase::PhaseFunction;
+use pdf::{PDF, power_heuristic};
+use light::{DeltaLight, PointLight, SpotLight, DirectionalLight};
+use light_bvh::LightBVH;
+use environment::{Background, EnvironmentMap};
+use sky::PhysicalSky;
+use ies::IesProfile;
+use bdpt::BidirectionalPathTracer;
+use photon::PhotonMapper;
+use light_tracer::LightTracer;
+
+// the parts of a scene the integrators trace against and sample lights from
+#[derive(Clone, Copy)]
+struct SceneRefs<'a> {
+    background: &'a dyn Background,
+    world: &'a dyn Hittable,
+    lights: &'a dyn Hittable,
+    delta_lights: &'a [Box<dyn DeltaLight>]
+}
 
-fn ray_color(ray: &Ray, background: Color, world: &Box<dyn Hittable>, lights: &Box<dyn Hittable>, depth: u64) -> Color {
-    if depth <= 0 {
-        // if we've exceeded the ray bounce limit, no more light is gathered
-        return Color::new(0.0, 0.0, 0.0)
+// point, spot and directional lights, which no ray can hit
+type DeltaLights = Vec<Box<dyn DeltaLight>>;
+
+// how much of the medium a path is in lets through between t_min and t_max
+fn medium_transmittance(medium: Option<&dyn Medium>, ray: &Ray, t_min: f64, t_max: f64) -> Color {
+    medium.map_or(Color::new(1.0, 1.0, 1.0), |medium| medium.transmittance(ray, t_min, t_max.min(1e12)))
+}
+
+// direct light from point, spot and directional lights, f_cos evaluates the brdf times cosine for a direction
+fn delta_light_color(ray: &Ray, rec: &HitRecord, scene: &SceneRefs, medium: Option<&dyn Medium>, f_cos: &impl Fn(&Ray) -> Color) -> Color {
+    scene.delta_lights.iter().filter_map(|light| light.sample_li(rec.position)).map(|sample| {
+        let shadow_ray = Ray::new(rec.position, sample.direction, ray.time());
+        let t_max = sample.distance * (1.0 - 1e-6);
+        let transmittance = scene.world.transmittance(&shadow_ray, 0.001, t_max) * medium_transmittance(medium, &shadow_ray, 0.001, t_max);
+        if transmittance.near_zero() {
+            Color::new(0.0, 0.0, 0.0)
+        } else {
+            f_cos(&shadow_ray) * sample.irradiance * transmittance
+        }
+    }).sum()
+}
+
+// radiance arriving along a bsdf sample, without tracing further than the first hit that is not crossing a medium,
+// weighted against light sampling
+fn incident_radiance(ray: &Ray, bsdf_pdf: f64, scene: &SceneRefs, medium: Option<&dyn Medium>) -> Color {
+    let origin = ray.origin();
+    let mut ray = Ray::new(ray.origin(), ray.direction(), ray.time());
+    let mut weight = Color::new(1.0, 1.0, 1.0);
+    loop {
+        let rec = match scene.world.hit(&ray, 0.00001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => {
+                let light_weight = power_heuristic(bsdf_pdf, scene.lights.pdf_value(origin, ray.direction()));
+                return weight * scene.background.color(&ray) * medium_transmittance(medium, &ray, 0.00001, f64::INFINITY) * light_weight
+            }
+        };
+        weight = weight * rec.weight * medium_transmittance(medium, &ray, 0.00001, rec.t);
+        match rec.material.scatter_mc_method(&ray, &rec) {
+            Some(ScatterRecord::PassThrough { ray: through }) => ray = through,
+            _ => return weight * rec.material.emitted(&ray, &rec) * emission_weight(Some(bsdf_pdf), origin, &ray, &rec, scene.lights)
+        }
     }
+}
 
-    // 0.001 t_min fixs shadow acne
-    if let Some(rec) = world.hit(ray, 0.00001, f64::INFINITY) {
-        // Color::new(1.0, 0.0, 0.0)
+// weight of emission a bsdf sample ran into against light sampling, which only finds points on the lights
+fn emission_weight(bsdf_pdf: Option<f64>, origin: Point3, ray: &Ray, rec: &HitRecord, lights: &dyn Hittable) -> f64 {
+    let bsdf_pdf = match bsdf_pdf {
+        Some(bsdf_pdf) => bsdf_pdf,
+        None => return 1.0
+    };
+    match lights.hit(ray, 0.00001, f64::INFINITY) {
+        Some(light) if (light.position - rec.position).length() <= 1e-7 * light.t.max(1.0) => power_heuristic(bsdf_pdf, lights.pdf_value(origin, ray.direction())),
+        _ => 1.0
+    }
+}
 
-        // 0.5 * (rec.normal + Color::new(1.0, 1.0, 1.0))
+// radiance from the lights along a light sample, dimmed by media on the way and blocked by surfaces
+fn light_radiance(ray: &Ray, scene: &SceneRefs, medium: Option<&dyn Medium>) -> Color {
+    let (radiance, t_max) = match scene.lights.hit(ray, 0.00001, f64::INFINITY) {
+        Some(rec) => (rec.material.emitted(ray, &rec), rec.t * (1.0 - 1e-6)),
+        None => (scene.background.color(ray), f64::INFINITY)
+    };
+    radiance * scene.world.transmittance(ray, 0.00001, t_max) * medium_transmittance(medium, ray, 0.00001, t_max)
+}
 
-        // Lambertian:
-        // let target = rec.position + rec.normal + Vec3::random_in_unit_sphere().normalized();
-        
-        // Hemispherical scattering:
-        // let target = rec.position + Vec3::random_in_hemisphere(rec.normal);
+// one light sample weighted against bsdf sampling with the power heuristic, plus every delta light; f_cos evaluates the brdf times cosine
+fn direct_light(ray: &Ray, rec: &HitRecord, pdf: &PDF, scene: &SceneRefs, medium: Option<&dyn Medium>, f_cos: &impl Fn(&Ray) -> Color) -> Color {
+    let mut color = delta_light_color(ray, rec, scene, medium, f_cos);
+
+    let light_ray = Ray::new(rec.position, scene.lights.random(rec.position).normalized(), ray.time());
+    let light_pdf = scene.lights.pdf_value(rec.position, light_ray.direction());
+    if light_pdf > 0.0 {
+        let f = f_cos(&light_ray);
+        if !f.near_zero() {
+            let weight = power_heuristic(light_pdf, pdf.value(light_ray.direction()));
+            color += f * light_radiance(&light_ray, scene, medium) * weight / light_pdf;
+        }
+    }
 
-        // let r = Ray::new(rec.position, target - rec.position);
-        // 0.5 * ray_color(&r, world, depth - 1)
-
-        let emitted: Color = rec.material.emitted(&rec);
-
-        // sample light
-        // let on_light = Point3::new(255.0, 554.0, 277.0);
-        // let mut to_light = on_light - rec.position;
-        // let distance_squared = to_light.length().powi(2);
-        // to_light = to_light.normalized();
-
-        // if to_light.dot(rec.normal) < 0.0 {
-        //     return emitted
-        // }
-
-        // let light_area = (343.0 - 213.0) * (332.0 - 227.0);
-        // let light_cosine = f64::abs(to_light.y());
-        // if light_cosine < 0.000001 {
-        //     return emitted
-        // }
-
-        // let pdf_value = distance_squared / (light_cosine * light_area);
-        // let scattered = Ray::new(rec.position, to_light, ray.time());
-
-        // old method
-        // if let Some((attenuation, scattered)) = rec.material.scatter(ray, &rec) {
-        //     emitted + attenuation * ray_color(&scattered, background, world, lights, depth - 1)
-        if let Some(srec) = rec.material.scatter_mc_method(ray, &rec) {
-
-            match srec {
-                ScatterRecord::Specular { specular_ray, attenuation } => {
-                    return attenuation * ray_color(&specular_ray, background, world, lights, depth - 1)
-                }
-                ScatterRecord::Scatter { pdf, attenuation } => {
-                    let hittable_pdf = PDF::hittable_pdf(rec.position, lights);
-                    let mixture_pdf = PDF::mixture_pdf(&hittable_pdf, &pdf);
-                    let scattered = Ray::new(rec.position, mixture_pdf.generate(), ray.time());
-                    let pdf_value = mixture_pdf.value(scattered.direction());
-                    return emitted + attenuation *  rec.material.scattering_pdf(ray, &rec, &scattered) * ray_color(&scattered, background, world, lights, depth - 1) / pdf_value
-                }
-                ScatterRecord::Microfacet { pdf } => {
-                    let hittable_pdf = PDF::hittable_pdf(rec.position, lights);
-                    let mixture_pdf = PDF::mixture_pdf(&hittable_pdf, &pdf);
-                    let scattered = Ray::new(rec.position, mixture_pdf.generate(), ray.time());
-                    let pdf_value = mixture_pdf.value(scattered.direction());
-                    return emitted + rec.material.brdf(ray, &scattered, &rec) * ray_color(&scattered, background, world, lights, depth - 1) / pdf_value
-                }
-           }
+    color
+}
 
-        } else {
-            emitted
+#[derive(Clone, Copy)]
+enum Bounce {
+    Diffuse,
+    Glossy,
+    Transmission,
+    Volume
+}
+
+// maximum number of bounces of each type along a path
+#[derive(Clone, Copy)]
+struct BounceLimits {
+    total: u64,
+    diffuse: u64,
+    glossy: u64,
+    transmission: u64,
+    volume: u64,
+    // bounces before russian roulette may end a path
+    russian_roulette: u64
+}
+
+impl BounceLimits {
+    fn limit(&self, bounce: Bounce) -> u64 {
+        match bounce {
+            Bounce::Diffuse => self.diffuse,
+            Bounce::Glossy => self.glossy,
+            Bounce::Transmission => self.transmission,
+            Bounce::Volume => self.volume
         }
+    }
+}
 
-    } else {
-    // let unit_direction = ray.direction().normalized();
-    // let t = 0.5 * (unit_direction.y() + 1.0);
+// bsdf sampling of the next direction, returns the ray, f * cos / pdf and the pdf
+fn sample_bsdf(ray: &Ray, rec: &HitRecord, pdf: &PDF, f_cos: &impl Fn(&Ray) -> Color) -> Option<(Ray, Color, f64)> {
+    let scattered = Ray::new(rec.position, pdf.generate()?.normalized(), ray.time());
+    let bsdf_pdf = pdf.value(scattered.direction());
+    if bsdf_pdf <= 0.0 {
+        return None
+    }
+    let f = f_cos(&scattered);
+    if f.near_zero() {
+        return None
+    }
+    Some((scattered, f / bsdf_pdf, bsdf_pdf))
+}
 
-    // //lerp white and blue with direction of y
-    // (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
-    background
+fn ray_color(ray: &Ray, scene: &SceneRefs, limits: &BounceLimits) -> Color {
+    let mut rng = rand::thread_rng();
+    let mut color = Color::new(0.0, 0.0, 0.0);
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = Ray::new(ray.origin(), ray.direction(), ray.time());
+    // pdf the ray direction was sampled with, None for camera rays and specular bounces which light sampling can not reach
+    let mut bsdf_pdf: Option<f64> = None;
+    // where that direction was sampled, light pdfs are measured from there even after crossing media
+    let mut origin = ray.origin();
+    let mut stack = InteriorStack::default();
+    let mut bounces = [0; 4];
+    // only scattering counts towards the limits, crossing into or out of a medium is not a bounce
+    let mut depth = 0;
+
+    while depth < limits.total {
+        // 0.001 t_min fixs shadow acne
+        let rec = match stack.hit(scene.world, &ray) {
+            Some(rec) => rec,
+            None => {
+                // weight the background against the chance of light sampling having found it
+                let weight = bsdf_pdf.map_or(1.0, |bsdf_pdf| power_heuristic(bsdf_pdf, scene.lights.pdf_value(origin, ray.direction())));
+                color += throughput * scene.background.color(&ray) * weight;
+                break
+            }
+        };
+        throughput = throughput * rec.weight;
+
+        let emitted = rec.material.emitted(&ray, &rec);
+        if !emitted.near_zero() {
+            color += throughput * emitted * emission_weight(bsdf_pdf, origin, &ray, &rec, scene.lights);
+        }
+
+        let srec = match stack.scatter(&ray, &rec) {
+            Some(srec) => srec,
+            None => break
+        };
+
+        let next = match srec {
+            ScatterRecord::PassThrough { ray: through } => {
+                ray = through;
+                continue
+            }
+            ScatterRecord::Specular { specular_ray, attenuation } => {
+                let bounce = if specular_ray.direction().dot(rec.normal) < 0.0 { Bounce::Transmission } else { Bounce::Glossy };
+                Some((specular_ray, attenuation, None, bounce))
+            }
+            ScatterRecord::Scatter { pdf, attenuation } => {
+                let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                color += throughput * direct_light(&ray, &rec, &pdf, scene, stack.medium(), &f_cos);
+                sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, pdf)| (scattered, weight, Some(pdf), Bounce::Diffuse))
+            }
+            ScatterRecord::Microfacet { pdf, eta } => {
+                let f_cos = |scattered: &Ray| rec.material.bsdf(&ray, scattered, &rec, eta) * rec.normal.dot(scattered.direction()).abs();
+                color += throughput * direct_light(&ray, &rec, &pdf, scene, stack.medium(), &f_cos);
+                sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, pdf)| {
+                    let bounce = if scattered.direction().dot(rec.normal) < 0.0 { Bounce::Transmission } else { Bounce::Glossy };
+                    (scattered, weight, Some(pdf), bounce)
+                })
+            }
+            ScatterRecord::Volume { pdf, attenuation } => {
+                let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                color += throughput * direct_light(&ray, &rec, &pdf, scene, stack.medium(), &f_cos);
+                sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, pdf)| (scattered, weight, Some(pdf), Bounce::Volume))
+            }
+        };
+
+        let (next_ray, weight, next_pdf, bounce) = match next {
+            Some(next) => next,
+            None => break
+        };
+        bounces[bounce as usize] += 1;
+        if bounces[bounce as usize] > limits.limit(bounce) {
+            break
+        }
+
+        stack.cross(&rec, next_ray.direction());
+        throughput = throughput * weight;
+        if depth >= limits.russian_roulette {
+            // paths carrying little light are ended early, survivors are scaled up to stay unbiased
+            let survival = throughput.x().max(throughput.y()).max(throughput.z()).min(0.95);
+            if rng.gen::<f64>() >= survival {
+                break
+            }
+            throughput = throughput / survival;
+        }
+
+        ray = next_ray;
+        bsdf_pdf = next_pdf;
+        origin = ray.origin();
+        depth += 1;
     }
+
+    color
 }
 
 fn progress_ray_color(ray: &Ray, background: Color, world: &Box<dyn Hittable>, depth: u64) -> Color {
@@ -244,6 +421,13 @@ fn two_perlin_sphere() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     (Box::new(world), Box::new(lights))
 }
 
+fn sunlit_perlin_sphere() -> (Box<dyn Hittable>, Box<dyn Hittable>, DeltaLights) {
+    let (world, lights) = two_perlin_sphere();
+    // the sun is about 0.53 degrees wide, which gives the shadow a soft edge
+    let sun = DirectionalLight::new(Vec3::new(-1.0, -1.5, -0.5), Color::new(1.0, 0.95, 0.9), 3.0, 0.53);
+    (world, lights, vec![Box::new(sun)])
+}
+
 fn earth() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     let image = image::open("earthmap.jpg").expect("image not found").to_rgb8();
     let (width ,height) = image.dimensions();
@@ -275,6 +459,32 @@ fn light_room() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     (Box::new(world), Box::new(lights))
 }
 
+fn tv_room() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let bottom_mat = Lambertian::new(ConstantTexture::new(Color::new(0.7, 0.7, 0.7)));
+    let sphere_mat = Lambertian::new(ConstantTexture::new(Color::new(0.8, 0.8, 0.8)));
+    world.push(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, bottom_mat));
+    world.push(Sphere::new(Point3::new(0.0, 1.5, 0.0), 1.5, sphere_mat));
+
+    // a screen showing an image, scaled so it is brighter than its 8 bit texture
+    let image = image::open("earthmap.jpg").expect("image not found").to_rgb8();
+    let (width ,height) = image.dimensions();
+    let screen = DiffuseLight::new(ImageTexture::new(image.into_raw(), width, height)).scaled(3.0);
+    let tv = Translate::new(Rotate::new(Axis::Y, AARect::new(Plane::XY, -3.0, 3.0, 0.5, 3.5, 0.0, screen), 90.0), Vec3::new(-4.0, 0.0, 0.0));
+
+    // a warm panel behind the sphere, its back face lights the sphere
+    let panel = AARect::new(Plane::XY, -2.0, 2.0, 2.5, 4.5, 5.0, DiffuseLight::blackbody(2700.0, 4.0).two_sided());
+
+    world.push(tv.clone());
+    world.push(panel.clone());
+    lights.push(tv);
+    lights.push(panel);
+
+    (Box::new(world), Box::new(lights))
+}
+
 fn cornell_box() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     let mut world = HittableList::default();
     let mut lights = HittableList::default();
@@ -337,7 +547,8 @@ fn cornell_box_with_smoke() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
             Rotate::new(Axis::Y,
                         Cube::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white),15.0), Vec3::new(265.0, 0.0, 295.0));
 
-    world.push(ConstantMedium::new(box1, 0.01, ConstantTexture::new(Color::new(1.0, 1.0, 1.0))));
+    // smoke scatters mostly forward
+    world.push(ConstantMedium::new(box1, 0.01, ConstantTexture::new(Color::new(1.0, 1.0, 1.0))).with_phase(PhaseFunction::HenyeyGreenstein { g: 0.4 }));
     world.push(ConstantMedium::new(box2, 0.01, ConstantTexture::new(Color::new(0.0, 0.0, 0.0))));
 
     lights.push(rect_light);
@@ -345,6 +556,304 @@ fn cornell_box_with_smoke() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     (Box::new(world), Box::new(lights))
 }
 
+fn cornell_clouds() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // marbled smoke filling most of the box, shadowing the floor where it is dense
+    let boundary = Cube::new(Point3::new(100.0, 0.0, 100.0), Point3::new(455.0, 350.0, 455.0), Dielectric::new(1.0));
+    let density = TextureDensity::new(NoiseTexture::new(0.05), 0.05);
+    // a strong forward lobe with some back scattering, as measured for cloud droplets
+    let phase = PhaseFunction::DoubleHenyeyGreenstein { g_forward: 0.8, g_backward: -0.3, weight: 0.9 };
+    world.push(HeterogeneousMedium::new(boundary, density, ConstantTexture::new(Color::new(0.9, 0.9, 0.9))).with_phase(phase));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_metals() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // rougher to the right
+    world.push(Sphere::new(Point3::new(95.0, 60.0, 300.0), 60.0, Conductor::silver(0.1)));
+    world.push(Sphere::new(Point3::new(215.0, 60.0, 300.0), 60.0, Conductor::gold(0.25)));
+    world.push(Sphere::new(Point3::new(335.0, 60.0, 300.0), 60.0, Conductor::copper(0.4)));
+    world.push(Sphere::new(Point3::new(455.0, 60.0, 300.0), 60.0, Conductor::aluminium(0.6)));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_frosted() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // evenly frosted green glass, and clear glass frosted in veins of a marble texture
+    let green_glass = HomogeneousMedium::new(Color::new(0.006, 0.002, 0.006), Color::new(0.0, 0.0, 0.0));
+    world.push(Sphere::new(Point3::new(190.0, 90.0, 190.0), 90.0, RoughDielectric::new(1.5, ConstantTexture::new(Color::new(0.2, 0.2, 0.2))).with_medium(green_glass)));
+    world.push(Sphere::new(Point3::new(380.0, 90.0, 340.0), 90.0, RoughDielectric::new(1.5, NoiseTexture::new(0.05))));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_principled() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // clearcoated plastic, brushed gold, frosted tinted glass and a thin translucent shell
+    let plastic = PBR::new(ConstantTexture::new(Color::new(0.8, 0.1, 0.1)), 0.0, 0.0, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 1.0, 0.9);
+    let gold = PBR::new(ConstantTexture::new(Color::new(1.0, 0.78, 0.34)), 1.0, 0.0, 0.5, 0.4, 0.0, 0.8, 0.0, 0.0, 0.0, 0.0);
+    let glass = PBR::new(ConstantTexture::new(Color::new(0.7, 0.85, 1.0)), 0.0, 0.0, 0.5, 0.3, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+        .with_transmission(1.0, 1.5);
+    let shell = PBR::new(ConstantTexture::new(Color::new(0.95, 0.9, 0.8)), 0.0, 0.0, 0.5, 0.2, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+        .with_transmission(0.6, 1.5)
+        .thin();
+
+    world.push(Sphere::new(Point3::new(95.0, 60.0, 300.0), 60.0, plastic));
+    world.push(Sphere::new(Point3::new(215.0, 60.0, 300.0), 60.0, gold));
+    world.push(Sphere::new(Point3::new(335.0, 60.0, 300.0), 60.0, glass));
+    world.push(Sphere::new(Point3::new(455.0, 60.0, 300.0), 60.0, shell));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_coated() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // blue plastic, candy red paint over aluminium flakes, lacquered marble and satin coated gold
+    let plastic = Coated::new(Lambertian::new(ConstantTexture::new(Color::new(0.9, 0.9, 0.9))), 1.5, 0.0)
+        .with_absorption(Color::new(2.0, 1.0, 0.1), 1.0);
+    let paint = Coated::new(Conductor::aluminium(0.5), 1.5, 0.0)
+        .with_absorption(Color::new(0.1, 2.5, 2.0), 1.0);
+    let marble = Coated::new(Lambertian::new(NoiseTexture::new(0.05)), 1.5, 0.15);
+    let gold = Coated::new(Conductor::gold(0.2), 1.5, 0.3);
+
+    world.push(Sphere::new(Point3::new(95.0, 60.0, 300.0), 60.0, plastic));
+    world.push(Sphere::new(Point3::new(215.0, 60.0, 300.0), 60.0, paint));
+    world.push(Sphere::new(Point3::new(335.0, 60.0, 300.0), 60.0, marble));
+    world.push(Sphere::new(Point3::new(455.0, 60.0, 300.0), 60.0, gold));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_mix() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // rust patches on polished steel, copper going green and red plastic as a diffuse plus a dielectric gloss lobe
+    let rusty = Mix::new(
+        Metal::new(Color::new(0.8, 0.8, 0.85), 0.05),
+        Lambertian::new(ConstantTexture::new(Color::new(0.45, 0.2, 0.08))),
+        NoiseTexture::new(0.05)
+    );
+    let patina = Mix::new(
+        Conductor::copper(0.25),
+        Lambertian::new(ConstantTexture::new(Color::new(0.3, 0.6, 0.5))),
+        NoiseTexture::new(0.1)
+    );
+    let plastic = Blend::new(
+        Lambertian::new(ConstantTexture::new(Color::new(0.6, 0.05, 0.05))),
+        Conductor::new(Color::new(1.5, 1.5, 1.5), Color::new(0.0, 0.0, 0.0), 0.2)
+    );
+
+    world.push(Sphere::new(Point3::new(130.0, 80.0, 300.0), 80.0, rusty));
+    world.push(Sphere::new(Point3::new(278.0, 80.0, 300.0), 80.0, patina));
+    world.push(Sphere::new(Point3::new(426.0, 80.0, 300.0), 80.0, plastic));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_plume() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // a smoke plume from a voxel grid, rising from the floor and turned towards the camera
+    let grid = VoxelGrid::load("volumes/plume.vol").with_bounds(Point3::new(-120.0, 0.0, -120.0), Point3::new(120.0, 360.0, 120.0)).with_scale(0.08);
+    let (min, max) = grid.bounds();
+    let boundary = Cube::new(min, max, Dielectric::new(1.0));
+    let plume = HeterogeneousMedium::new(boundary, grid, ConstantTexture::new(Color::new(0.8, 0.8, 0.8))).with_phase(PhaseFunction::HenyeyGreenstein { g: 0.3 });
+    world.push(Translate::new(Rotate::new(Axis::Y, plume, 35.0), Vec3::new(278.0, 0.0, 298.0)));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_fire() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // the plume as dark smoke, its flames burn near the floor and cool off as it rises
+    let (min, max) = (Point3::new(158.0, 0.0, 178.0), Point3::new(398.0, 360.0, 418.0));
+    let smoke = VoxelGrid::load("volumes/plume.vol").with_bounds(min, max).with_scale(0.08);
+    let temperature = VoxelGrid::load_raw("volumes/plume_temperature.raw", [24, 36, 24]).with_bounds(min, max).with_scale(1900.0);
+    let boundary = Cube::new(min, max, Dielectric::new(1.0));
+    world.push(HeterogeneousMedium::new(boundary, smoke, ConstantTexture::new(Color::new(0.3, 0.3, 0.3)))
+        .with_emission(BlackbodyEmission::new(temperature, 8.0)));
+    // a cloud of glowing soot that swallows blue the most, what is behind it shows through reddened
+    let ember = Sphere::new(Point3::new(450.0, 50.0, 150.0), 50.0, Dielectric::new(1.0));
+    let heat = TextureDensity::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0)), 1500.0);
+    world.push(ChromaticMedium::new(ember, Color::new(0.004, 0.01, 0.03), Color::new(0.002, 0.002, 0.002))
+        .with_emission(BlackbodyEmission::new(heat, 4.0)));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_glow() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // an even haze that only glows where the plume grid says, like a cloud lit from within
+    let (min, max) = (Point3::new(158.0, 0.0, 178.0), Point3::new(398.0, 360.0, 418.0));
+    let glow = VoxelGrid::load("volumes/plume.vol").with_bounds(min, max);
+    world.push(ConstantMedium::new(Cube::new(min, max, white), 0.01, ConstantTexture::new(Color::new(0.5, 0.5, 0.5)))
+        .with_emission(EmissionField::new(glow, Color::new(8.0, 3.2, 0.8))));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
 fn cornell_test() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     let mut world = HittableList::default();
     let mut lights = HittableList::default();
@@ -430,24 +939,278 @@ fn cornell_test() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     let tri0 = Triangle::new([Vec3::new(0.0, 0.0, 465.0), Vec3::new(555.0, 0.0, 465.0), Vec3::new(278.0, 455.0, 555.0)], metal);   
     let obj = Mesh::load_obj("Venus.obj", Vec3::new(278.0, 3.0, 258.0), 0.2, color_80cf00).unwrap();
 
-    world.push(rect_light0.clone());
-    //world.push(rect_light1.clone());
-    //world.push(rect_light2.clone());
-    //world.push(rect_light3.clone());
-    //world.push(rect_light4.clone());
-    //world.push(mirror);
-    //world.push(spehre0);
-    //world.push(Translate::new(Rotate::new(Axis::Y, cube0, 30.0), Vec3::new(278.0, 0.0, 156.0)));
-    //world.push(tri0);  
-    world.push(BVH::new(obj.tris.list, 0.0, 1.0));
+    world.push(rect_light0.clone());
+    //world.push(rect_light1.clone());
+    //world.push(rect_light2.clone());
+    //world.push(rect_light3.clone());
+    //world.push(rect_light4.clone());
+    //world.push(mirror);
+    //world.push(spehre0);
+    //world.push(Translate::new(Rotate::new(Axis::Y, cube0, 30.0), Vec3::new(278.0, 0.0, 156.0)));
+    //world.push(tri0);  
+    world.push(BVH::new(obj.tris.list, 0.0, 1.0));
+
+    lights.push(rect_light0);
+    //lights.push(rect_light1);
+    //lights.push(rect_light2);
+    //lights.push(rect_light3);
+    //lights.push(rect_light4);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_teapot() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+
+    // the low-poly teapot smoothed by two levels of loop subdivision
+    let teapot = Mesh::load_obj_subdivided("teapot.obj", Vec3::new(278.0, 100.0, 278.0), 2.5, Subdivision::Loop(2), white.clone()).unwrap();
+    world.push(BVH::new(teapot.tris.list, 0.0, 1.0));
+
+    // a quad cube cage rounded off by three levels of catmull-clark
+    let pebble = Mesh::load_obj_subdivided("cube.obj", Vec3::new(110.0, 50.5, 150.0), 60.0, Subdivision::CatmullClark(3), white).unwrap();
+    world.push(BVH::new(pebble.tris.list, 0.0, 1.0));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_spot() -> (Box<dyn Hittable>, Box<dyn Hittable>, DeltaLights) {
+    let mut world = HittableList::default();
+    let lights = HittableList::default();
+    let mut delta_lights: DeltaLights = Vec::new();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(
+        Translate::new(
+            Rotate::new(Axis::Y,
+                        Cube::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white),-18.0), Vec3::new(130.0, 0.0, 65.0)));
+    world.push(
+        Translate::new(
+            Rotate::new(Axis::Y,
+                        Cube::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white),15.0), Vec3::new(265.0, 0.0, 295.0)));
+
+    // power in watts, the box is in units of millimeters so it is scaled by 1000^2
+    delta_lights.push(Box::new(SpotLight::new(Point3::new(278.0, 550.0, 278.0), Point3::new(278.0, 0.0, 278.0), Color::new(1.0, 0.95, 0.85), 2.0e5, 20.0, 35.0)));
+    delta_lights.push(Box::new(PointLight::new(Point3::new(100.0, 450.0, 100.0), Color::new(0.6, 0.7, 1.0), 4.0e4)));
+
+    (Box::new(world), Box::new(lights), delta_lights)
+}
+
+fn cornell_caustics() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    // a small bright light keeps the caustics sharp
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(60.0, 60.0, 60.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 248.0, 308.0, 248.0, 308.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+    world.push(Sphere::new(Point3::new(190.0, 100.0, 190.0), 100.0, Dielectric::new(1.5)));
+    world.push(Sphere::new(Point3::new(390.0, 80.0, 330.0), 80.0, Metal::new(Color::new(0.8, 0.85, 0.88), 0.0)));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_liquids() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // wine only absorbs, so the light through it is tinted by beer lambert without any noise
+    let wine = HomogeneousMedium::new(Color::new(0.002, 0.03, 0.025), Color::new(0.0, 0.0, 0.0));
+    world.push(Sphere::new(Point3::new(190.0, 100.0, 190.0), 100.0, Dielectric::new(1.33).with_medium(wine)));
+    // milky and bare so light sampling reaches inside, blue scatters the most and light coming out deep inside turns red
+    let milk = Sphere::new(Point3::new(390.0, 90.0, 330.0), 90.0, Dielectric::new(1.0));
+    world.push(ChromaticMedium::new(milk, Color::new(0.0005, 0.001, 0.003), Color::new(0.02, 0.035, 0.05)).with_phase(PhaseFunction::HenyeyGreenstein { g: 0.7 }));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_nested() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(15.0, 15.0, 15.0)));
+
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, light));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+
+    // a glass ball holding water, the water overrides the glass inside it and refracts from glass rather than air
+    let water = HomogeneousMedium::new(Color::new(0.004, 0.0015, 0.001), Color::new(0.0, 0.0, 0.0));
+    world.push(Sphere::new(Point3::new(190.0, 100.0, 190.0), 100.0, Dielectric::new(1.5).with_priority(1)));
+    world.push(Sphere::new(Point3::new(190.0, 100.0, 190.0), 85.0, Dielectric::new(1.33).with_medium(water).with_priority(2)));
+    // a frosted glass block with an air bubble in it, the bubble's clear surface refracts from glass
+    world.push(Cube::new(Point3::new(300.0, 0.0, 260.0), Point3::new(460.0, 160.0, 420.0), RoughDielectric::new(1.5, ConstantTexture::new(Color::new(0.15, 0.15, 0.15))).with_priority(1)));
+    world.push(Sphere::new(Point3::new(380.0, 80.0, 340.0), 50.0, Dielectric::new(1.0).with_priority(2)));
+
+    lights.push(rect_light);
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn cornell_ies() -> (Box<dyn Hittable>, Box<dyn Hittable>, DeltaLights) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+    let mut delta_lights: DeltaLights = Vec::new();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+
+    // ceiling panel with a batwing distribution, brightest around 45 degrees off the normal
+    let panel = DiffuseLight::new(ConstantTexture::new(Color::new(12.0, 12.0, 12.0))).with_profile(IesProfile::load("ies/batwing.ies"));
+    let rect_light = FlipNormal::new(AARect::new(Plane::XZ, 213.0, 343.0, 227.0, 332.0, 554.0, panel));
+
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(rect_light.clone());
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
+    world.push(AARect::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone()));
+    world.push(
+        Translate::new(
+            Rotate::new(Axis::Y,
+                        Cube::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white.clone()),-18.0), Vec3::new(130.0, 0.0, 65.0)));
+    world.push(
+        Translate::new(
+            Rotate::new(Axis::Y,
+                        Cube::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white),15.0), Vec3::new(265.0, 0.0, 295.0)));
+
+    lights.push(rect_light);
+
+    // the wall washer throws its light toward -x onto the red wall
+    delta_lights.push(Box::new(PointLight::new(Point3::new(60.0, 520.0, 278.0), Color::new(1.0, 0.9, 0.75), 8.0e4).with_profile(IesProfile::load("ies/wallwash.ies"))));
+    delta_lights.push(Box::new(SpotLight::new(Point3::new(212.0, 540.0, 147.0), Point3::new(212.0, 165.0, 147.0), Color::new(0.8, 0.9, 1.0), 1.0e5, 15.0, 30.0).with_profile(IesProfile::load("ies/downlight.ies"))));
+
+    (Box::new(world), Box::new(lights), delta_lights)
+}
+
+// rough glass against a uniform white background, it should vanish but for the energy single scattering microfacets
+// lose, which grows with roughness
+fn furnace() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut world = HittableList::default();
+    let lights = HittableList::default();
+
+    for (i, roughness) in [0.1, 0.3, 0.5].into_iter().enumerate() {
+        let center = Point3::new(2.2 * (i as f64 - 1.0), 0.0, 0.0);
+        world.push(Sphere::new(center, 1.0, RoughDielectric::new(1.5, ConstantTexture::new(Color::new(roughness, roughness, roughness)))));
+    }
+
+    (Box::new(world), Box::new(lights))
+}
+
+fn many_lights() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let mut rng = rand::thread_rng();
+    let mut world: Vec<Box<dyn Hittable>> = Vec::new();
+    let mut lights = HittableList::default();
+
+    let ground = Lambertian::new(ConstantTexture::new(Color::new(0.5, 0.5, 0.5)));
+    world.push(Box::new(AARect::new(Plane::XZ, -50.0, 50.0, -50.0, 50.0, 0.0, ground)));
+    world.push(Box::new(Sphere::new(Point3::new(-4.2, 2.0, 0.0), 2.0, Lambertian::new(ConstantTexture::new(Color::new(0.7, 0.3, 0.1))))));
+    world.push(Box::new(Sphere::new(Point3::new(0.0, 2.0, 0.0), 2.0, Dielectric::new(1.5))));
+    world.push(Box::new(Sphere::new(Point3::new(4.2, 2.0, 0.0), 2.0, Metal::new(Color::new(0.8, 0.85, 0.88), 0.05))));
+
+    // hundreds of small emitters, too many to sample uniformly
+    for _ in 0..500 {
+        let center = Point3::new(rng.gen_range(-20.0..20.0), rng.gen_range(0.3..6.0), rng.gen_range(-20.0..10.0));
+        let emit = DiffuseLight::new(ConstantTexture::new(Color::random(0.2..1.0) * rng.gen_range(5.0..40.0)));
+        let sphere = Sphere::new(center, rng.gen_range(0.05..0.2), emit);
+        world.push(Box::new(sphere.clone()));
+        lights.push(sphere);
+    }
+
+    (Box::new(BVH::new(world, 0.0, 1.0)), Box::new(LightBVH::new(lights)))
+}
+
+fn spheres_on_ground(world: &mut HittableList) {
+    let ground = Lambertian::new(CheckTexture::new(ConstantTexture::new(Color::new(0.2, 0.3, 0.1)), ConstantTexture::new(Color::new(0.9, 0.9, 0.9))));
+    world.push(AARect::new(Plane::XZ, -50.0, 50.0, -50.0, 50.0, 0.0, ground));
+    world.push(Sphere::new(Point3::new(-4.2, 2.0, 0.0), 2.0, Lambertian::new(ConstantTexture::new(Color::new(0.7, 0.3, 0.1)))));
+    world.push(Sphere::new(Point3::new(0.0, 2.0, 0.0), 2.0, Dielectric::new(1.5)));
+    world.push(Sphere::new(Point3::new(4.2, 2.0, 0.0), 2.0, Metal::new(Color::new(0.8, 0.85, 0.88), 0.05)));
+}
 
-    lights.push(rect_light0);
-    //lights.push(rect_light1);
-    //lights.push(rect_light2);
-    //lights.push(rect_light3);
-    //lights.push(rect_light4);
+fn environment_spheres() -> (Box<dyn Hittable>, Box<dyn Hittable>, EnvironmentMap) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+    spheres_on_ground(&mut world);
 
-    (Box::new(world), Box::new(lights))
+    // the environment lights the scene through escaped rays and is importance sampled as a light
+    let environment = EnvironmentMap::new("sky.hdr", 0.35, 0.0);
+    lights.push(environment.clone());
+
+    (Box::new(world), Box::new(lights), environment)
+}
+
+fn sky_spheres() -> (Box<dyn Hittable>, Box<dyn Hittable>, PhysicalSky, DeltaLights) {
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+    spheres_on_ground(&mut world);
+
+    // late afternoon sun, the sky is in kcd/m^2 so scale it down to a displayable range
+    let sky = PhysicalSky::new(25.0, 200.0, 3.0, 0.05);
+    lights.push(sky.clone());
+
+    (Box::new(world), Box::new(lights), sky.clone(), vec![Box::new(sky.sun())])
 }
 
 fn final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
@@ -481,12 +1244,13 @@ fn final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>) {
     world.push(Sphere::new(Point3::new(260.0, 150.0, 45.0), 50.0, Dielectric::new(1.5)));
     world.push(Sphere::new(Point3::new(0.0, 150.0, 145.0), 50.0, Metal::new(Color::new(0.8, 0.8, 0.9), 1.0)));
 
-    let boundary = Sphere::new(Point3::new(360.0, 150.0, 145.0), 70.0, Dielectric::new(1.5));
-    world.push(boundary.clone());
-    world.push(ConstantMedium::new(boundary, 0.2, ConstantTexture::new(Color::new(0.2, 0.4, 0.9))));
+    // glass filled with a medium scattering blue the most
+    let filling = HomogeneousMedium::new(Color::new(0.16, 0.12, 0.02), Color::new(0.04, 0.08, 0.18));
+    world.push(Sphere::new(Point3::new(360.0, 150.0, 145.0), 70.0, Dielectric::new(1.5).with_medium(filling)));
 
     let boundary = Sphere::new(Point3::new(0.0, 0.0, 0.0), 5000.0, Dielectric::new(1.5));
-    world.push(ConstantMedium::new(boundary, 0.0001, ConstantTexture::new(Color::new(1.0, 1.0, 1.0))));
+    // thin air around everything
+    world.push(ConstantMedium::new(boundary, 0.0001, ConstantTexture::new(Color::new(1.0, 1.0, 1.0))).with_phase(PhaseFunction::Rayleigh));
 
     let image = image::open("earthmap.jpg").expect("image not found").to_rgb8();
     let (nx, ny) = image.dimensions();
@@ -565,22 +1329,60 @@ enum Scene {
     Random,
     TwoSphere,
     TwoPerlinSphere,
+    SunlitPerlinSphere,
     Earth,
     LightRoom,
+    TvRoom,
     CornellBox,
     CornellSmoke,
+    CornellClouds,
+    CornellMetals,
+    CornellFrosted,
+    CornellPrincipled,
+    CornellCoated,
+    CornellMix,
+    CornellPlume,
+    CornellFire,
+    CornellGlow,
     CornellTest,
+    CornellTeapot,
+    CornellSpot,
+    CornellIes,
+    CornellCaustics,
+    CornellLiquids,
+    CornellNested,
+    ManyLights,
+    EnvironmentSpheres,
+    SkySpheres,
+    Furnace,
     FinalScene,
     Progress
 }
 
+enum Integrator {
+    PathTracing,
+    // max_depth bounds the number of bounces of the connected paths
+    Bidirectional { max_depth: usize },
+    // photons traced per pass, one pass per sample, radius of the first density estimate in scene units
+    PhotonMapping { photons: usize, radius: f64, max_depth: usize },
+    // only paths from the lights splatted through the lens, a check against path tracing; mirrors and glass seen by the camera stay black
+    LightTracing { max_depth: usize }
+}
+
 fn main() {
     // image
     const ASPECT_RATIO: f64 = 1.0;
     const IMAGE_WIDTH: u64 = 500;
     const IMAGE_HEIGHT: u64 = ((IMAGE_WIDTH as f64) / ASPECT_RATIO) as u64;
     const SAMPLES_PER_PIXEL: u64 = 800;
-    const MAX_DEPTH: u64 = 100;
+    const BOUNCE_LIMITS: BounceLimits = BounceLimits {
+        total: 100,
+        diffuse: 8,
+        glossy: 16,
+        transmission: 32,
+        volume: 64,
+        russian_roulette: 3
+    };
 
     // world
     // let mut world = World::new();
@@ -621,11 +1423,19 @@ fn main() {
     // let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - Vec3::new(0.0, 0.0, focal_length);
 
     let scene: Scene = Scene::CornellTest;
-    let (world, background, lights, camera) = match scene {
+    // scenes lit mostly along paths the camera side finds poorly come with the integrator made for them
+    let integrator = match scene {
+        Scene::CornellCaustics => Integrator::PhotonMapping { photons: 200000, radius: 8.0, max_depth: 32 },
+        Scene::CornellFrosted => Integrator::Bidirectional { max_depth: 32 },
+        // only diffuse surfaces lit by a spot and a point light, every pixel is reached from the lamps
+        Scene::CornellSpot => Integrator::LightTracing { max_depth: 32 },
+        _ => Integrator::PathTracing
+    };
+    let (world, background, lights, delta_lights, camera) = match scene {
         Scene::Random => {
             let (world, lights) = final_scene();
 
-            let backgournd = Color::new(0.7, 0.8, 1.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.7, 0.8, 1.0));
 
             let lookfrom = Point3::new(13.0, 2.0, 3.0);
             let lookat = Point3::new(0.0, 0.0, 0.0);
@@ -634,12 +1444,12 @@ fn main() {
             let aperture = 0.1;
             let camera = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::TwoSphere =>{
             let (world, lights) = two_spehre();
 
-            let backgournd = Color::new(0.7, 0.8, 1.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.7, 0.8, 1.0));
 
             let lookfrom = Point3::new(13.0, 2.0, 3.0);
             let lookat = Point3::new(0.0, 0.0, 0.0);
@@ -648,12 +1458,26 @@ fn main() {
             let aperture = 0.0;
             let camera = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::TwoPerlinSphere => {
             let (world, lights) = two_perlin_sphere();
 
-            let backgournd = Color::new(0.7, 0.8, 1.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.7, 0.8, 1.0));
+
+            let lookfrom = Point3::new(1013.0, 2.0, 1003.0);
+            let lookat = Point3::new(1000.0, 0.0, 1000.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::SunlitPerlinSphere => {
+            let (world, lights, delta_lights) = sunlit_perlin_sphere();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.2, 0.25, 0.35));
 
             let lookfrom = Point3::new(1013.0, 2.0, 1003.0);
             let lookat = Point3::new(1000.0, 0.0, 1000.0);
@@ -662,12 +1486,12 @@ fn main() {
             let aperture = 0.0;
             let camera = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, delta_lights, camera)
         }
         Scene::Earth => {
             let (world, lights) = earth();
 
-            let backgournd = Color::new(0.7, 0.8, 1.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.7, 0.8, 1.0));
 
             let lookfrom = Point3::new(13.0, 2.0, 3.0);
             let lookat = Point3::new(0.0, 0.0, 0.0);
@@ -676,12 +1500,12 @@ fn main() {
             let aperture = 0.1;
             let camera = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::LightRoom => {
             let (world, lights) = light_room();
 
-            let backgournd = Color::new(0.0, 0.0, 0.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
 
             let lookfrom = Point3::new(26.0, 3.0, 6.0);
             let lookat = Point3::new(0.0, 2.0, 0.0);
@@ -690,12 +1514,26 @@ fn main() {
             let aperture = 0.0;
             let camera = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::TvRoom => {
+            let (world, lights) = tv_room();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(26.0, 3.0, 6.0);
+            let lookat = Point3::new(0.0, 2.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 25.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::CornellBox => {
             let (world, lights) = cornell_box();
             
-            let backgournd = Color::new(0.0, 0.0, 0.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
 
             let lookfrom = Point3::new(278.0, 278.0, -800.0);
             let lookat = Point3::new(278.0, 278.0, 0.0);
@@ -704,12 +1542,12 @@ fn main() {
             let aperture = 0.05;
             let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::CornellSmoke => {
             let (world, lights) = cornell_box_with_smoke();
             
-            let backgournd = Color::new(0.0, 0.0, 0.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
 
             let lookfrom = Point3::new(278.0, 278.0, -800.0);
             let lookat = Point3::new(278.0, 278.0, 0.0);
@@ -718,12 +1556,138 @@ fn main() {
             let aperture = 0.05;
             let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellClouds => {
+            let (world, lights) = cornell_clouds();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellMetals => {
+            let (world, lights) = cornell_metals();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellFrosted => {
+            let (world, lights) = cornell_frosted();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellPrincipled => {
+            let (world, lights) = cornell_principled();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellCoated => {
+            let (world, lights) = cornell_coated();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellMix => {
+            let (world, lights) = cornell_mix();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellPlume => {
+            let (world, lights) = cornell_plume();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellFire => {
+            let (world, lights) = cornell_fire();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellGlow => {
+            let (world, lights) = cornell_glow();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::CornellTest => {
             let (world, lights) = cornell_test();
             
-            let backgournd = Color::new(0.0, 0.0, 0.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
 
             let lookfrom = Point3::new(199.0, 439.0, -200.0);
             let lookat = Point3::new(278.0, 375.0, 258.0);
@@ -732,12 +1696,152 @@ fn main() {
             let aperture = 0.01;
             let camera = Camera::new(lookfrom, lookat, vup, 30.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellTeapot => {
+            let (world, lights) = cornell_teapot();
+            
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.05;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellSpot => {
+            let (world, lights, delta_lights) = cornell_spot();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.05;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, delta_lights, camera)
+        }
+        Scene::CornellIes => {
+            let (world, lights, delta_lights) = cornell_ies();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.05;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, delta_lights, camera)
+        }
+        Scene::CornellCaustics => {
+            let (world, lights) = cornell_caustics();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellLiquids => {
+            let (world, lights) = cornell_liquids();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::CornellNested => {
+            let (world, lights) = cornell_nested();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(278.0, 278.0, -800.0);
+            let lookat = Point3::new(278.0, 278.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::ManyLights => {
+            let (world, lights) = many_lights();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+
+            let lookfrom = Point3::new(0.0, 6.0, 18.0);
+            let lookat = Point3::new(0.0, 1.5, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 18.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::EnvironmentSpheres => {
+            let (world, lights, environment) = environment_spheres();
+
+            let backgournd: Box<dyn Background> = Box::new(environment);
+
+            let lookfrom = Point3::new(0.0, 6.0, 18.0);
+            let lookat = Point3::new(0.0, 1.5, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
+        }
+        Scene::SkySpheres => {
+            let (world, lights, sky, delta_lights) = sky_spheres();
+
+            let backgournd: Box<dyn Background> = Box::new(sky);
+
+            let lookfrom = Point3::new(0.0, 6.0, 18.0);
+            let lookat = Point3::new(0.0, 1.5, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, delta_lights, camera)
+        }
+        Scene::Furnace => {
+            let (world, lights) = furnace();
+
+            let backgournd: Box<dyn Background> = Box::new(Color::new(1.0, 1.0, 1.0));
+
+            let lookfrom = Point3::new(0.0, 0.0, 12.0);
+            let lookat = Point3::new(0.0, 0.0, 0.0);
+            let vup = Vec3::new(0.0, 1.0, 0.0);
+            let dist_to_focus = 10.0;
+            let aperture = 0.0;
+            let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
+
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::FinalScene => {
             let (world, lights) = final_scene();
             
-            let backgournd = Color::new(0.0, 0.0, 0.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
 
             let lookfrom = Point3::new(478.0, 278.0, -600.0);
             let lookat = Point3::new(278.0, 278.0, 0.0);
@@ -746,12 +1850,12 @@ fn main() {
             let aperture = 0.01;
             let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
         }
         Scene::Progress => {
             let (world, lights) = progress_showcase();
 
-            let backgournd = Color::new(0.0, 0.0, 0.0);
+            let backgournd: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
 
             let lookfrom = Point3::new(-3.3, 6.8, -9.8);
             let lookat = Point3::new(0.0, 1.0, 0.0);
@@ -760,7 +1864,7 @@ fn main() {
             let aperture = 0.2;
             let camera = Camera::new(lookfrom, lookat, vup, 40.0, ASPECT_RATIO, aperture, dist_to_focus, 0.0, 1.0);
 
-            (world, backgournd, lights, camera)
+            (world, backgournd, lights, Vec::new(), camera)
         }
     };
 
@@ -768,6 +1872,36 @@ fn main() {
     println!("{} {}",IMAGE_WIDTH, IMAGE_HEIGHT);
     println!("255");
 
+    let refs = SceneRefs {
+        background: background.as_ref(),
+        world: world.as_ref(),
+        lights: lights.as_ref(),
+        delta_lights: &delta_lights
+    };
+
+    // light and photon paths only start at the lights, the background would light nothing but what the camera sees directly
+    if matches!(integrator, Integrator::PhotonMapping { .. } | Integrator::LightTracing { .. }) && background.emits() {
+        panic!("light tracing and photon mapping can not render scenes lit by their background, use path tracing");
+    }
+    if let Integrator::Bidirectional { max_depth } = integrator {
+        let tracer = BidirectionalPathTracer::new(&camera, refs, max_depth);
+        tracer.render(IMAGE_WIDTH as usize, IMAGE_HEIGHT as usize, SAMPLES_PER_PIXEL).write_ppm(SAMPLES_PER_PIXEL);
+        eprintln!("\nDone.");
+        return
+    }
+    if let Integrator::PhotonMapping { photons, radius, max_depth } = integrator {
+        let mapper = PhotonMapper::new(&camera, refs, photons, radius, max_depth);
+        mapper.render(IMAGE_WIDTH as usize, IMAGE_HEIGHT as usize, SAMPLES_PER_PIXEL).write_ppm(SAMPLES_PER_PIXEL);
+        eprintln!("\nDone.");
+        return
+    }
+    if let Integrator::LightTracing { max_depth } = integrator {
+        let tracer = LightTracer::new(&camera, refs, max_depth);
+        tracer.render(IMAGE_WIDTH as usize, IMAGE_HEIGHT as usize, SAMPLES_PER_PIXEL).write_ppm(SAMPLES_PER_PIXEL);
+        eprintln!("\nDone.");
+        return
+    }
+
     //let mut rng = rand::thread_rng();
     for j in (0..IMAGE_HEIGHT).rev() {
         //adding a progress indicator
@@ -814,8 +1948,9 @@ fn main() {
                 let random_u = rng.gen::<f64>();
                 let random_v = rng.gen::<f64>();
 
-                let u = ((i as f64) + random_u) / ((IMAGE_WIDTH - 1) as f64);
-                let v = ((j as f64) + random_v) / ((IMAGE_HEIGHT - 1) as f64);
+                // the same film coordinates the bidirectional tracer splats to
+                let u = ((i as f64) + random_u) / (IMAGE_WIDTH as f64);
+                let v = ((j as f64) + random_v) / (IMAGE_HEIGHT as f64);
 
                 let r = camera.get_ray(u, v);
 
@@ -824,7 +1959,7 @@ fn main() {
                 // //lerp white and blue with direction of y
                 // let backgournd = (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
 
-                ray_color(&r, background, &world, &lights, MAX_DEPTH)
+                ray_color(&r, &refs, &BOUNCE_LIMITS)
                 // progress_ray_color(&r, background, &world, MAX_DEPTH)
             })
             .sum();
@@ -834,3 +1969,91 @@ fn main() {
     }
     eprintln!("Done.");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::Film;
+
+    const WIDTH: usize = 12;
+    const SAMPLES_PER_PIXEL: u64 = 32;
+    // camera paths seldom land on the small light, the reference needs many more of them
+    const REFERENCE_SAMPLES: u64 = 2048;
+
+    fn cornell_camera() -> Camera {
+        Camera::new(Point3::new(278.0, 278.0, -800.0), Point3::new(278.0, 278.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 40.0, 1.0, 0.0, 10.0, 0.0, 1.0)
+    }
+
+    // average luminance of a tiny path traced image
+    fn path_traced(camera: &Camera, scene: &SceneRefs) -> f64 {
+        let limits = BounceLimits { total: 32, diffuse: 32, glossy: 32, transmission: 32, volume: 32, russian_roulette: 3 };
+        let total: Color = (0..WIDTH * WIDTH).into_par_iter().map(|pixel| {
+            let mut rng = rand::thread_rng();
+            (0..REFERENCE_SAMPLES).map(|_| {
+                let u = ((pixel % WIDTH) as f64 + rng.gen::<f64>()) / WIDTH as f64;
+                let v = ((pixel / WIDTH) as f64 + rng.gen::<f64>()) / WIDTH as f64;
+                ray_color(&camera.get_ray(u, v), scene, &limits)
+            }).sum::<Color>()
+        }).sum();
+        total.luminance() / (WIDTH * WIDTH) as f64 / REFERENCE_SAMPLES as f64
+    }
+
+    fn film_mean(film: &Film) -> f64 {
+        film.sum().luminance() / (WIDTH * WIDTH) as f64 / SAMPLES_PER_PIXEL as f64
+    }
+
+    fn assert_close(name: &str, value: f64, reference: f64, tolerance: f64) {
+        let error = (value - reference).abs() / reference;
+        assert!(error < tolerance, "{} gives {}, path tracing {}", name, value, reference);
+    }
+
+    #[test]
+    fn bidirectional_matches_path_tracing() {
+        let (world, lights) = cornell_box();
+        let background: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+        let delta_lights: DeltaLights = Vec::new();
+        let refs = SceneRefs { background: background.as_ref(), world: world.as_ref(), lights: lights.as_ref(), delta_lights: &delta_lights };
+        let camera = cornell_camera();
+
+        let reference = path_traced(&camera, &refs);
+        let film = BidirectionalPathTracer::new(&camera, refs, 32).render(WIDTH, WIDTH, SAMPLES_PER_PIXEL);
+        assert_close("bidirectional path tracing", film_mean(&film), reference, 0.05);
+    }
+
+    #[test]
+    fn sunlight_reaches_light_paths() {
+        let mut world = HittableList::default();
+        let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+        world.push(AARect::new(Plane::XZ, -2.0, 2.0, -2.0, 2.0, 0.0, white.clone()));
+        world.push(Sphere::new(Point3::new(0.0, 0.5, 0.0), 0.5, white));
+        let world: Box<dyn Hittable> = Box::new(world);
+        let lights: Box<dyn Hittable> = Box::new(HittableList::default());
+        let sun = DirectionalLight::new(Vec3::new(-1.0, -1.5, -0.5), Color::new(1.0, 0.95, 0.9), 3.0, 0.53);
+        let delta_lights: DeltaLights = vec![Box::new(sun)];
+        let background: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+        let refs = SceneRefs { background: background.as_ref(), world: world.as_ref(), lights: lights.as_ref(), delta_lights: &delta_lights };
+        let camera = Camera::new(Point3::new(0.0, 3.0, 3.0), Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 40.0, 1.0, 0.0, 10.0, 0.0, 1.0);
+
+        let reference = path_traced(&camera, &refs);
+        // most of the disk the sun shoots through lies outside the view, light tracing needs more paths
+        let film = LightTracer::new(&camera, refs, 32).render(WIDTH, WIDTH, 4 * SAMPLES_PER_PIXEL);
+        assert_close("light tracing", film_mean(&film) / 4.0, reference, 0.05);
+        let film = PhotonMapper::new(&camera, refs, 20000, 0.2, 32).render(WIDTH, WIDTH, SAMPLES_PER_PIXEL);
+        assert_close("photon mapping", film_mean(&film), reference, 0.1);
+    }
+
+    #[test]
+    fn light_paths_match_path_tracing() {
+        let (world, lights, delta_lights) = cornell_spot();
+        let background: Box<dyn Background> = Box::new(Color::new(0.0, 0.0, 0.0));
+        let refs = SceneRefs { background: background.as_ref(), world: world.as_ref(), lights: lights.as_ref(), delta_lights: &delta_lights };
+        let camera = cornell_camera();
+
+        let reference = path_traced(&camera, &refs);
+        let film = LightTracer::new(&camera, refs, 32).render(WIDTH, WIDTH, SAMPLES_PER_PIXEL);
+        assert_close("light tracing", film_mean(&film), reference, 0.05);
+        // the density estimate blurs, so photon mapping gets a wider margin
+        let film = PhotonMapper::new(&camera, refs, 20000, 20.0, 32).render(WIDTH, WIDTH, SAMPLES_PER_PIXEL);
+        assert_close("photon mapping", film_mean(&film), reference, 0.1);
+    }
+}
@@ -3,9 +3,15 @@ use std::f64;
 use super::vec::{Vec3, Color};
 use super::ray::Ray;
 use super::hit::{HitRecord};
-use super::texture::Texture;
-use super::pdf::PDF;
+use super::texture::{Texture, ConstantTexture};
+use super::pdf::{PDF, power_heuristic};
+use super::phase::PhaseFunction;
 use super::onb::ONB;
+use super::ies::IesProfile;
+use super::spectrum::blackbody;
+use super::interior::Interior;
+use super::medium::{Medium, Emission};
+use std::sync::Arc;
 
 pub fn schlick_fresnel(u: f64) -> f64 {
     let m = (1.0 - u).clamp(0.0, 1.0);
@@ -19,7 +25,7 @@ pub fn GTR_1(n_dot_h: f64, a: f64) -> f64 {
     } else {
         let a2 = a * a;
         let t = 1.0 + (a2 - 1.0) * n_dot_h * n_dot_h;
-        return (a2 - 1.0) / (f64::consts::PI * a2.log2() * t);
+        return (a2 - 1.0) / (f64::consts::PI * a2.ln() * t);
     }
 }
 
@@ -39,8 +45,55 @@ pub fn smithG_GGX(n_dot_v: f64, alphaG: f64) -> f64 {
     return 1.0 / (n_dot_v + f64::sqrt(a + b - a * b));
 }
 
-pub fn smithG_GGX_aniso(n_dot_v: f64, v_dot_x: f64, v_dot_y: f64, ax: f64, ay: f64) -> f64 {
-    return 1.0 / (n_dot_v + f64::sqrt((v_dot_x * ax).powi(2) + (v_dot_y * ay).powi(2) + n_dot_v.powi(2)));
+// smith lambda of the isotropic GGX distribution for a direction at cos_theta to the normal
+pub fn ggx_lambda(cos_theta: f64, alpha: f64) -> f64 {
+    let cos2 = (cos_theta * cos_theta).max(1e-12);
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+// the same for the anisotropic distribution, with the direction's components along the normal and the two tangents
+pub fn ggx_lambda_aniso(n_dot_w: f64, w_dot_x: f64, w_dot_y: f64, ax: f64, ay: f64) -> f64 {
+    let cos2 = (n_dot_w * n_dot_w).max(1e-12);
+    0.5 * ((1.0 + ((w_dot_x * ax).powi(2) + (w_dot_y * ay).powi(2)) / cos2).sqrt() - 1.0)
+}
+
+// fraction of the microfacets facing a direction that it sees unmasked
+pub fn ggx_g1(cos_theta: f64, alpha: f64) -> f64 {
+    1.0 / (1.0 + ggx_lambda(cos_theta, alpha))
+}
+
+// height correlated masking and shadowing of the directions toward the viewer and the light
+pub fn ggx_g2(cos_o: f64, cos_i: f64, alpha: f64) -> f64 {
+    1.0 / (1.0 + ggx_lambda(cos_o, alpha) + ggx_lambda(cos_i, alpha))
+}
+
+// exact fresnel reflectance of a conductor with complex index of refraction eta + ik, for unpolarized light
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+// exact fresnel reflectance between dielectrics, eta is the index of refraction on the incident side over the other one
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
 }
 
 pub fn mon_to_lin(x: Vec3) -> Vec3 {
@@ -66,20 +119,59 @@ pub trait Material: Sync {
         0.0
     }
 
-    fn emitted(&self, rec: &HitRecord) -> Color {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
 
+    // average luminance of the emitted radiance, used to estimate light power
+    fn emitted_luminance(&self) -> f64 {
+        0.0
+    }
+
+    fn emits_both_sides(&self) -> bool {
+        false
+    }
+
+    // the volume a closed dielectric surface bounds, None for everything else
+    fn interior(&self) -> Option<Interior<'_>> {
+        None
+    }
+
+    // scattering between two volumes, eta is the index of refraction on the side of r_in over the one on the other side
+    fn scatter_interface(&self, r_in: &Ray, rec: &HitRecord, _eta: f64) -> Option<ScatterRecord> {
+        self.scatter_mc_method(r_in, rec)
+    }
+
     //choose disney principled brdf
     fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    // brdf and btdf of a surface between two volumes, eta as in scatter_interface
+    fn bsdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord, _eta: f64) -> Vec3 {
+        self.brdf(r_in, r_out, rec)
+    }
 }
 
 pub enum ScatterRecord<'a> {
     Specular { specular_ray: Ray, attenuation: Color },
     Scatter { pdf: PDF<'a>, attenuation: Color },
-    Microfacet { pdf: PDF<'a> }
+    // evaluated with bsdf, eta is the one the record was made with and 1 for opaque surfaces
+    Microfacet { pdf: PDF<'a>, eta: f64 },
+    // scattering inside a participating medium, attenuation is the single scattering albedo
+    Volume { pdf: PDF<'a>, attenuation: Color },
+    // not a bounce, the ray goes on unchanged and light sampling still belongs to the vertex before
+    PassThrough { ray: Ray }
+}
+
+// f times the cosine toward wi of a record the material made for light arriving along r_in
+fn record_f_cos<M: Material + ?Sized>(material: &M, record: &ScatterRecord, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+    let scattered = Ray::new(rec.position, wi, r_in.time());
+    match record {
+        ScatterRecord::Scatter { attenuation, .. } => *attenuation * material.scattering_pdf(r_in, rec, &scattered),
+        ScatterRecord::Microfacet { eta, .. } => material.bsdf(r_in, &scattered, rec, *eta) * rec.normal.dot(wi).abs(),
+        _ => Color::new(0.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -94,7 +186,12 @@ pub struct PBR<T: Texture> {
     sheen: f64,
     sheen_tint: f64,
     clearcoat: f64,
-    clearcoat_gloss: f64
+    clearcoat_gloss: f64,
+    // share of the dielectric part refracted through the surface instead of scattered diffusely
+    spec_trans: f64,
+    ior: f64,
+    // an infinitely thin sheet, transmitted light leaves from the other side without entering a volume
+    thin: bool
 }
 
 impl<T: Texture> PBR<T> {
@@ -111,52 +208,185 @@ impl<T: Texture> PBR<T> {
             sheen_tint,
             clearcoat,
             clearcoat_gloss,
+            spec_trans: 0.0,
+            ior: 1.5,
+            thin: false
+        }
+    }
+
+    pub fn with_transmission(self, spec_trans: f64, ior: f64) -> PBR<T> {
+        PBR {
+            spec_trans,
+            ior,
+            ..self
+        }
+    }
+
+    pub fn thin(self) -> PBR<T> {
+        PBR {
+            thin: true,
+            ..self
+        }
+    }
+
+    // GGX alphas along the two tangents of the shading frame
+    fn alpha(&self) -> (f64, f64) {
+        let aspect = (1.0 - self.anisotropic * 0.9).sqrt();
+        ((self.roughness.powi(2) / aspect).max(0.001), (self.roughness.powi(2) * aspect).max(0.001))
+    }
+
+    // weights of the diffuse and the transmission lobes
+    fn weights(&self) -> (f64, f64) {
+        ((1.0 - self.metallic) * (1.0 - self.spec_trans), (1.0 - self.metallic) * self.spec_trans)
+    }
+
+    fn air_ratio(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face || self.thin { 1.0 / self.ior } else { self.ior }
+    }
+
+    // a thin sheet has air on both sides, so light always meets it going in
+    fn side(&self, wo: Vec3, rec: &HitRecord, eta: f64) -> (Vec3, f64) {
+        if self.thin { (side_of(wo, rec, eta).0, 1.0 / self.ior) } else { side_of(wo, rec, eta) }
+    }
+
+    fn base(&self, rec: &HitRecord) -> Color {
+        mon_to_lin(self.base_color.mapping(rec.u, rec.v, &rec.position))
+    }
+
+    fn tint(cd_lin: Color) -> Color {
+        //luminance approx
+        let cd_lum = 0.3 * cd_lin.x() + 0.6 * cd_lin.y() + 0.1 * cd_lin.z();
+        if cd_lum > 0.0 { cd_lin / cd_lum } else { Vec3::new(1.0, 1.0, 1.0) }
+    }
+
+    // light passes the surface twice on its way through a body, so each crossing takes the square root of the color
+    fn transmission_tint(cd_lin: Color) -> Color {
+        Color::new(cd_lin.x().sqrt(), cd_lin.y().sqrt(), cd_lin.z().sqrt())
+    }
+
+    // schlick toward the specular color on the opaque part, exact fresnel of the ior on the transmissive one
+    fn specular_fresnel(&self, cos: f64, eta: f64, cd_lin: Color) -> Color {
+        let c_spec0 = (Vec3::new(1.0, 1.0, 1.0).mix(Self::tint(cd_lin), self.specular_tint) * 0.08 * self.specular).mix(cd_lin, self.metallic);
+        let (_, transmission) = self.weights();
+        let fresnel = fresnel_dielectric(cos, eta) * transmission;
+        c_spec0.mix(Vec3::new(1.0, 1.0, 1.0), schlick_fresnel(cos)) * (1.0 - transmission) + Vec3::new(fresnel, fresnel, fresnel)
+    }
+
+    // rough share of the diffuse, specular, transmission and clearcoat lobes in the light leaving toward wo,
+    // the probabilities of sampling them
+    fn lobes(&self, cos_o: f64, eta: f64, cd_lin: Color) -> [f64; 4] {
+        let (diffuse, transmission) = self.weights();
+        let lobes = [
+            diffuse * cd_lin.luminance(),
+            self.specular_fresnel(cos_o, eta, cd_lin).luminance(),
+            transmission * (1.0 - fresnel_dielectric(cos_o, eta)) * Self::transmission_tint(cd_lin).luminance(),
+            0.25 * self.clearcoat * mix(0.04, 1.0, schlick_fresnel(cos_o))
+        ];
+        let total: f64 = lobes.iter().sum();
+        if total <= 0.0 {
+            return [1.0, 0.0, 0.0, 0.0]
         }
+        lobes.map(|lobe| lobe / total)
     }
 }
 
+// thin sheets transmit through microfacets roughened by the index of refraction, after Burley 2015
+pub fn thin_transmission_alpha(alpha: (f64, f64), eta: f64) -> (f64, f64) {
+    let scale = (0.65 / eta - 0.35).powi(2);
+    ((alpha.0 * scale).max(0.001), (alpha.1 * scale).max(0.001))
+}
+
 impl<T: Texture> Material for PBR<T> {
     fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.scatter_interface(r_in, rec, self.air_ratio(rec))
+    }
 
-        let rec = ScatterRecord::Microfacet { 
-            pdf: PDF::brdf_pdf(rec.normal,
-                            r_in.direction(),
-                            self.roughness, 
-                            self.anisotropic,
-                            self.clearcoat,
-                            self.clearcoat_gloss
-                            )
-        };
+    fn interior(&self) -> Option<Interior<'_>> {
+        if self.spec_trans <= 0.0 || self.thin {
+            return None
+        }
+        Some(Interior {
+            ior: self.ior,
+            priority: 0,
+            medium: None
+        })
+    }
 
-        Some(rec)
+    fn scatter_interface(&self, r_in: &Ray, rec: &HitRecord, eta: f64) -> Option<ScatterRecord> {
+        let direction = r_in.direction().normalized();
+        let (normal, side_eta) = self.side((-1.0) * direction, rec, eta);
+        let cos_o = -direction.dot(normal);
+
+        Some(ScatterRecord::Microfacet {
+            pdf: PDF::principled_pdf(normal,
+                            direction,
+                            self.alpha(),
+                            mix(0.1, 0.001, self.clearcoat_gloss),
+                            side_eta,
+                            self.thin,
+                            self.lobes(cos_o, side_eta, self.base(rec))
+                            ),
+            eta
+        })
     }
 
     fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        self.bsdf(r_in, r_out, rec, self.air_ratio(rec))
+    }
+
+    fn bsdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord, eta: f64) -> Vec3 {
         //inverse direction
-        let l= r_in.direction().normalized() * (-1.0);
+        let l = r_in.direction().normalized() * (-1.0);
         let v = r_out.direction().normalized();
-        let onb = ONB::build_from_w(&rec.normal);
-        let n = onb.w();
+        let (n, eta) = self.side(l, rec, eta);
+        let onb = ONB::build_from_w(&n);
         let x = onb.u();
         let y = onb.v();
-    
+
         let n_dot_v = n.dot(v);
         let n_dot_l = n.dot(l);
-        if n_dot_l < 0.0 || n_dot_v < 0.0 {
+        if n_dot_l <= 0.0 || n_dot_v == 0.0 {
             return Vec3::new(0.0, 0.0, 0.0);
         }
 
+        let cd_lin = self.base(rec);
+        let (diffuse_weight, transmission_weight) = self.weights();
+        let (ax, ay) = self.alpha();
+
+        if n_dot_v < 0.0 {
+            if transmission_weight <= 0.0 {
+                return Vec3::new(0.0, 0.0, 0.0);
+            }
+            let c_trans = Self::transmission_tint(cd_lin) * transmission_weight;
+            let lambda_l = ggx_lambda_aniso(n_dot_l, l.dot(x), l.dot(y), ax, ay);
+
+            if self.thin {
+                // leaves like the reflection mirrored through the sheet
+                let (tx, ty) = thin_transmission_alpha((ax, ay), eta);
+                let h = (l + v - 2.0 * n_dot_v * n).normalized();
+                let d_trans = GTR_2_aniso(n.dot(h), h.dot(x), h.dot(y), tx, ty);
+                let g_trans = 1.0 / (1.0 + ggx_lambda_aniso(n_dot_l, l.dot(x), l.dot(y), tx, ty) + ggx_lambda_aniso(n_dot_v, v.dot(x), v.dot(y), tx, ty));
+                return c_trans * ((1.0 - fresnel_dielectric(l.dot(h), eta)) * d_trans * g_trans / (4.0 * n_dot_l * n_dot_v.abs()));
+            }
+
+            // walter et al. refraction through the same microfacets the specular lobe reflects off
+            let h = match refraction_half_vector(l, v, n, eta) {
+                Some(h) => h,
+                None => return Vec3::new(0.0, 0.0, 0.0)
+            };
+            let (l_dot_h, v_dot_h) = (l.dot(h), v.dot(h));
+            let denominator = (v_dot_h + eta * l_dot_h).powi(2);
+            let d_trans = GTR_2_aniso(n.dot(h), h.dot(x), h.dot(y), ax, ay);
+            let g_trans = 1.0 / (1.0 + lambda_l + ggx_lambda_aniso(n_dot_v, v.dot(x), v.dot(y), ax, ay));
+            return c_trans * ((1.0 - fresnel_dielectric(l_dot_h, eta)) * d_trans * g_trans
+                * (v_dot_h * l_dot_h / (n_dot_l * n_dot_v * denominator)).abs());
+        }
+
         let h = (l + v).normalized();
         let n_dot_h = n.dot(h);
         let l_dot_h = l.dot(h);
 
-        let cd_lin = mon_to_lin(self.base_color.mapping(rec.u, rec.v, &rec.position));
-        //luminance approx
-        let cd_lum = 0.3 * cd_lin.x() + 0.6 * cd_lin.y() + 0.1 * cd_lin.z();
-
-        let c_tint = if cd_lum > 0.0 { cd_lin / cd_lum } else { Vec3::new(1.0, 1.0, 1.0) };
-        let c_spec0 = (Vec3::new(1.0, 1.0, 1.0).mix(c_tint, self.specular_tint) * 0.08 * self.specular).mix(cd_lin, self.metallic);
-        let c_sheen = Vec3::new(1.0, 1.0, 1.0).mix(c_tint, self.sheen_tint);
+        let c_sheen = Vec3::new(1.0, 1.0, 1.0).mix(Self::tint(cd_lin), self.sheen_tint);
 
         // Diffuse fresnel - go from 1 at normal incidence to .5 at grazing
         // and mix in diffuse retro-reflection based on roughness
@@ -172,14 +402,12 @@ impl<T: Texture> Material for PBR<T> {
         let fresnel_subface_scatter = mix(1.0, fresnel_subface_scatter_90, fresnel_l) * mix(1.0, fresnel_subface_scatter_90, fresnel_v);
         let subface_scatter = 1.25 * (fresnel_subface_scatter * (1.0 / (n_dot_l + n_dot_v) - 0.5) + 0.5);
 
-        // specular
-        let aspect = (1.0 - self.anisotropic * 0.9).sqrt();
-        let ax = (self.roughness.powi(2) / aspect).max(0.001);
-        let ay = (self.roughness.powi(2) * aspect).max(0.001);
+        // specular, with height correlated masking and shadowing
         let d_specular = GTR_2_aniso(n_dot_h, h.dot(x), h.dot(y), ax, ay);
         let fresnel_h = schlick_fresnel(l_dot_h);
-        let f_specular = c_spec0.mix(Vec3::new(1.0, 1.0, 1.0), fresnel_h);
-        let g_specular = smithG_GGX_aniso(n_dot_l, l.dot(x), l.dot(y), ax, ay) * smithG_GGX_aniso(n_dot_v, v.dot(x), v.dot(y), ax, ay);
+        let f_specular = self.specular_fresnel(l_dot_h, eta, cd_lin);
+        let g_specular = 1.0 / (1.0 + ggx_lambda_aniso(n_dot_l, l.dot(x), l.dot(y), ax, ay) + ggx_lambda_aniso(n_dot_v, v.dot(x), v.dot(y), ax, ay))
+            / (4.0 * n_dot_l * n_dot_v);
 
         // sheen
         let fresnel_sheen = fresnel_h * self.sheen * c_sheen;
@@ -190,10 +418,9 @@ impl<T: Texture> Material for PBR<T> {
         let g_reflect = smithG_GGX(n_dot_l, 0.25) * smithG_GGX(n_dot_v, 0.25);
 
         return ((1.0 / f64::consts::PI) * mix(fresnel_diffuse, subface_scatter, self.subsurface) * cd_lin + fresnel_sheen)
-                * (1.0 - self.metallic)
+                * diffuse_weight
                 + g_specular * f_specular * d_specular + Vec3::new(0.25, 0.25, 0.25) * self.clearcoat * g_reflect * f_reflect * d_reflect;
     }
-    
 }
 
 #[derive(Clone, Copy)]
@@ -247,6 +474,14 @@ impl<T: Texture> Material for Lambertian<T> {
         let cosine = rec.normal.dot(scattered.direction().normalized()).max(0.0);
         cosine / f64::consts::PI
     }
+
+    // for materials that add it to other lobes, the integrators evaluate the scatter record
+    fn brdf(&self, _r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        if rec.normal.dot(r_out.direction()) <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+        self.albedo.mapping(rec.u, rec.v, &rec.position) / f64::consts::PI
+    }
 }
 
 
@@ -294,15 +529,117 @@ impl Material for Metal {
 }
 
 
+// a metal with a per channel complex index of refraction and GGX microfacets
 #[derive(Clone, Copy)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    // perceptual roughness, the GGX alpha is its square
+    roughness: f64
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            roughness
+        }
+    }
+
+    // indices of refraction at 650, 550 and 450 nm
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(Color::new(0.143, 0.374, 1.442), Color::new(3.983, 2.385, 1.603), roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(Color::new(0.200, 0.924, 1.102), Color::new(3.912, 2.452, 2.142), roughness)
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::new(Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147), roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::new(Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837), roughness)
+    }
+
+    fn alpha(&self) -> f64 {
+        self.roughness * self.roughness
+    }
+
+    fn fresnel(&self, cos_i: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_i, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_i, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_i, self.eta.z(), self.k.z())
+        )
+    }
+}
+
+// below this alpha the distribution is too sharp to evaluate, the surface is a mirror
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+impl Material for Conductor {
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let direction = r_in.direction().normalized();
+        if self.alpha() < SMOOTH_ALPHA {
+            let reflected = direction.reflect(rec.normal);
+            return Some(ScatterRecord::Specular {
+                specular_ray: Ray::new(rec.position, reflected, r_in.time()),
+                attenuation: self.fresnel(reflected.dot(rec.normal).clamp(0.0, 1.0))
+            })
+        }
+        Some(ScatterRecord::Microfacet {
+            pdf: PDF::ggx_pdf(rec.normal, direction, self.alpha()),
+            eta: 1.0
+        })
+    }
+
+    fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        let wo = r_in.direction().normalized() * (-1.0);
+        let wi = r_out.direction().normalized();
+        let n = rec.normal;
+        let (cos_o, cos_i) = (n.dot(wo), n.dot(wi));
+        if cos_o <= 0.0 || cos_i <= 0.0 || self.alpha() < SMOOTH_ALPHA {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+        let h = (wo + wi).normalized();
+        let d = GTR_2(n.dot(h), self.alpha());
+        let g = ggx_g2(cos_o, cos_i, self.alpha());
+        self.fresnel(wo.dot(h).clamp(0.0, 1.0)) * (d * g / (4.0 * cos_o * cos_i))
+    }
+}
+
+#[derive(Clone)]
 pub struct Dielectric {
-    ir: f64
+    ir: f64,
+    priority: u32,
+    // what the inside is filled with, clear unless given
+    medium: Option<Arc<dyn Medium>>
 }
 
 impl Dielectric {
     pub fn new(index_of_refraction: f64) -> Dielectric {
         Dielectric { 
-            ir: index_of_refraction
+            ir: index_of_refraction,
+            priority: 0,
+            medium: None
+        }
+    }
+
+    // where dielectrics overlap the one with the highest priority fills the overlap, like a glass around its water
+    pub fn with_priority(self, priority: u32) -> Dielectric {
+        Dielectric {
+            priority,
+            ..self
+        }
+    }
+
+    pub fn with_medium(self, medium: impl Medium + 'static) -> Dielectric {
+        Dielectric {
+            medium: Some(Arc::new(medium)),
+            ..self
         }
     }
 
@@ -340,14 +677,26 @@ impl Material for Dielectric {
         Some((Color::new(1.0, 1.0, 1.0), scattered))
     }
 
+    // seen from air on the outside
     fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if rec.front_face {
             1.0 / self.ir
         } else {
             self.ir
         };
+        self.scatter_interface(r_in, rec, refraction_ratio)
+    }
 
+    fn interior(&self) -> Option<Interior<'_>> {
+        Some(Interior {
+            ior: self.ir,
+            priority: self.priority,
+            medium: self.medium.as_deref()
+        })
+    }
+
+    fn scatter_interface(&self, r_in: &Ray, rec: &HitRecord, refraction_ratio: f64) -> Option<ScatterRecord> {
+        let attenuation = Color::new(1.0, 1.0, 1.0);
         let unit_direction = r_in.direction().normalized();
         
         let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
@@ -374,17 +723,650 @@ impl Material for Dielectric {
     }
 }
 
-#[derive(Clone, Copy)]
+// frosted glass, GGX microfacets that reflect and refract after Walter et al.
+#[derive(Clone)]
+pub struct RoughDielectric<T: Texture> {
+    ir: f64,
+    // perceptual roughness from the texture's luminance, the GGX alpha is its square
+    roughness: T,
+    priority: u32,
+    medium: Option<Arc<dyn Medium>>
+}
+
+impl<T: Texture> RoughDielectric<T> {
+    pub fn new(index_of_refraction: f64, roughness: T) -> RoughDielectric<T> {
+        RoughDielectric {
+            ir: index_of_refraction,
+            roughness,
+            priority: 0,
+            medium: None
+        }
+    }
+
+    pub fn with_priority(self, priority: u32) -> RoughDielectric<T> {
+        RoughDielectric {
+            priority,
+            ..self
+        }
+    }
+
+    pub fn with_medium(self, medium: impl Medium + 'static) -> RoughDielectric<T> {
+        RoughDielectric {
+            medium: Some(Arc::new(medium)),
+            ..self
+        }
+    }
+
+    fn alpha(&self, rec: &HitRecord) -> f64 {
+        self.roughness.mapping(rec.u, rec.v, &rec.position).luminance().clamp(0.0, 1.0).powi(2)
+    }
+
+    fn air_ratio(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face { 1.0 / self.ir } else { self.ir }
+    }
+}
+
+// the normal on the side of wo and the ratio of indices seen from there, the record is made for the side r_in came from
+// but light paths evaluate it from the other one too
+fn side_of(wo: Vec3, rec: &HitRecord, eta: f64) -> (Vec3, f64) {
+    if wo.dot(rec.normal) >= 0.0 { (rec.normal, eta) } else { ((-1.0) * rec.normal, 1.0 / eta) }
+}
+
+impl<T: Texture> Material for RoughDielectric<T> {
+    // seen from air on the outside
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.scatter_interface(r_in, rec, self.air_ratio(rec))
+    }
+
+    fn interior(&self) -> Option<Interior<'_>> {
+        Some(Interior {
+            ior: self.ir,
+            priority: self.priority,
+            medium: self.medium.as_deref()
+        })
+    }
+
+    fn scatter_interface(&self, r_in: &Ray, rec: &HitRecord, eta: f64) -> Option<ScatterRecord> {
+        let direction = r_in.direction().normalized();
+        let (normal, side_eta) = side_of((-1.0) * direction, rec, eta);
+        let alpha = self.alpha(rec);
+        if (side_eta - 1.0).abs() < 1e-6 {
+            // the same index on both sides, the surface can not be seen
+            return Some(ScatterRecord::Specular {
+                specular_ray: Ray::new(rec.position, direction, r_in.time()),
+                attenuation: Color::new(1.0, 1.0, 1.0)
+            })
+        }
+        if alpha < SMOOTH_ALPHA {
+            // too smooth for the distribution, reflect or refract about the normal itself
+            let cos_theta = ((-1.0) * direction).dot(normal).min(1.0);
+            let scattered = if rand::thread_rng().gen::<f64>() < fresnel_dielectric(cos_theta, side_eta) {
+                direction.reflect(normal)
+            } else {
+                direction.refract(normal, side_eta)
+            };
+            return Some(ScatterRecord::Specular {
+                specular_ray: Ray::new(rec.position, scattered, r_in.time()),
+                attenuation: Color::new(1.0, 1.0, 1.0)
+            })
+        }
+        Some(ScatterRecord::Microfacet {
+            pdf: PDF::rough_dielectric_pdf(normal, direction, alpha, side_eta),
+            eta
+        })
+    }
+
+    fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        self.bsdf(r_in, r_out, rec, self.air_ratio(rec))
+    }
+
+    fn bsdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord, eta: f64) -> Vec3 {
+        let wo = r_in.direction().normalized() * (-1.0);
+        let wi = r_out.direction().normalized();
+        let (n, eta) = side_of(wo, rec, eta);
+        let alpha = self.alpha(rec);
+        if alpha < SMOOTH_ALPHA {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+        let value = rough_dielectric_bsdf(wo, wi, n, eta, alpha);
+        Vec3::new(value, value, value)
+    }
+}
+
+// walter et al. reflection and refraction off GGX microfacets, for unit directions with wo on the side of n
+// and eta the index there over the other one
+pub fn rough_dielectric_bsdf(wo: Vec3, wi: Vec3, n: Vec3, eta: f64, alpha: f64) -> f64 {
+    let (cos_o, cos_i) = (n.dot(wo), n.dot(wi));
+    if cos_o <= 0.0 || cos_i == 0.0 {
+        return 0.0
+    }
+
+    if cos_i > 0.0 {
+        let h = (wo + wi).normalized();
+        fresnel_dielectric(wo.dot(h), eta) * GTR_2(n.dot(h), alpha) * ggx_g2(cos_o, cos_i.abs(), alpha) / (4.0 * cos_o * cos_i)
+    } else {
+        let h = match refraction_half_vector(wo, wi, n, eta) {
+            Some(h) => h,
+            None => return 0.0
+        };
+        let (o_dot_h, i_dot_h) = (wo.dot(h), wi.dot(h));
+        let denominator = (i_dot_h + eta * o_dot_h).powi(2);
+        (1.0 - fresnel_dielectric(o_dot_h, eta)) * GTR_2(n.dot(h), alpha) * ggx_g2(cos_o, cos_i.abs(), alpha)
+            * (i_dot_h * o_dot_h / (cos_o * cos_i * denominator)).abs()
+    }
+}
+
+// microfacet normal refracting wo into wi, on the side of n, None where it would face away from either of them
+pub fn refraction_half_vector(wo: Vec3, wi: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let h = wi + eta * wo;
+    if h.near_zero() {
+        return None
+    }
+    let h = if h.dot(n) < 0.0 { (-1.0) * h.normalized() } else { h.normalized() };
+    if wo.dot(h) <= 0.0 || wi.dot(h) >= 0.0 {
+        return None
+    }
+    Some(h)
+}
+
+// bounces between the coat and the base a random walk through a coated surface takes at most
+const LAYER_DEPTH: usize = 10;
+
+// a base material under a dielectric clear coat, with an absorbing layer of some thickness between the two.
+// light bouncing inside is evaluated by position free random walks after Guo et al. 2018, so bsdf returns
+// an unbiased estimate instead of the exact value. over a specular base, like a smooth metal or glass, there is
+// nothing to evaluate and the walk itself picks the way out, reflected by the coat or through it off the base
+#[derive(Clone)]
+pub struct Coated<M: Material> {
+    base: M,
+    ir: f64,
+    // perceptual roughness of the coat, the GGX alpha is its square
+    roughness: f64,
+    thickness: f64,
+    // absorption coefficient of the layer per unit of thickness
+    absorption: Color
+}
+
+impl<M: Material> Coated<M> {
+    pub fn new(base: M, index_of_refraction: f64, roughness: f64) -> Coated<M> {
+        Coated {
+            base,
+            ir: index_of_refraction,
+            roughness,
+            thickness: 0.0,
+            absorption: Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    pub fn with_absorption(self, absorption: Color, thickness: f64) -> Coated<M> {
+        Coated {
+            absorption,
+            thickness,
+            ..self
+        }
+    }
+
+    // a smooth coat stays the sharpest microfacet lobe, integrators evaluate the record of a surface more than once
+    // so it can not choose at random between a mirror and the layer under it
+    fn alpha(&self) -> f64 {
+        self.roughness.powi(2).max(SMOOTH_ALPHA)
+    }
+
+    // transmittance across the layer along w
+    fn transmittance(&self, w: Vec3, n: Vec3) -> Color {
+        let distance = self.thickness / w.dot(n).abs().max(1e-6);
+        Color::new((-self.absorption.x() * distance).exp(), (-self.absorption.y() * distance).exp(), (-self.absorption.z() * distance).exp())
+    }
+
+    // the rough coat's bsdf between unit directions on either side of it, n points out of the layer
+    fn coat(&self, wo: Vec3, wi: Vec3, n: Vec3) -> f64 {
+        let (n, eta) = if wo.dot(n) >= 0.0 { (n, 1.0 / self.ir) } else { ((-1.0) * n, self.ir) };
+        rough_dielectric_bsdf(wo, wi, n, eta, self.alpha())
+    }
+
+    fn base_pdf(record: &ScatterRecord, wi: Vec3) -> f64 {
+        match record {
+            ScatterRecord::Scatter { pdf, .. } | ScatterRecord::Microfacet { pdf, .. } => pdf.value(wi),
+            _ => 0.0
+        }
+    }
+
+    // the way out of a walk over a specular base with its f cos over pdf, from reflecting off the coat to bouncing
+    // inside the layer, None where the walk is absorbed. light the base refracts into itself goes on below
+    fn sample_through(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Color)> {
+        let wo = r_in.direction().normalized() * (-1.0);
+        let n = rec.normal;
+        let alpha = self.alpha();
+
+        let pdf = PDF::rough_dielectric_pdf(n, (-1.0) * wo, alpha, 1.0 / self.ir);
+        let mut w = pdf.generate()?;
+        let value = pdf.value(w);
+        if value <= 0.0 {
+            return None
+        }
+        let mut beta = Color::new(1.0, 1.0, 1.0) * (self.coat(wo, w, n) * n.dot(w).abs() / value);
+        if w.dot(n) > 0.0 {
+            return Some((w, beta))
+        }
+
+        for _ in 0..LAYER_DEPTH {
+            beta = beta * self.transmittance(w, n);
+            let (specular_ray, attenuation) = match self.base.scatter_mc_method(&Ray::new(rec.position, w, r_in.time()), rec) {
+                Some(ScatterRecord::Specular { specular_ray, attenuation }) => (specular_ray, attenuation),
+                _ => return None
+            };
+            w = specular_ray.direction().normalized();
+            beta = beta * attenuation;
+            if w.dot(n) <= 0.0 {
+                return Some((w, beta))
+            }
+            beta = beta * self.transmittance(w, n);
+
+            // out through the coat or back down off its underside
+            let pdf = PDF::rough_dielectric_pdf((-1.0) * n, w, alpha, self.ir);
+            let next = pdf.generate()?;
+            let value = pdf.value(next);
+            if value <= 0.0 {
+                return None
+            }
+            beta *= self.coat((-1.0) * w, next, n) * n.dot(next).abs() / value;
+            w = next;
+            if w.dot(n) > 0.0 {
+                return Some((w, beta))
+            }
+        }
+        None
+    }
+
+    // a direction sampled from the base's record with f cos over pdf, and the pdf unless the record is specular
+    fn sample_base(&self, record: &ScatterRecord, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Color, Option<f64>)> {
+        match record {
+            ScatterRecord::Specular { specular_ray, attenuation } => Some((specular_ray.direction().normalized(), *attenuation, None)),
+            ScatterRecord::Scatter { pdf, .. } | ScatterRecord::Microfacet { pdf, .. } => {
+                let wi = pdf.generate()?.normalized();
+                let value = pdf.value(wi);
+                if value <= 0.0 {
+                    return None
+                }
+                Some((wi, record_f_cos(&self.base, record, r_in, rec, wi) / value, Some(value)))
+            }
+            _ => None
+        }
+    }
+}
+
+impl<M: Material> Material for Coated<M> {
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let direction = r_in.direction().normalized();
+        let fresnel = fresnel_dielectric(-direction.dot(rec.normal), 1.0 / self.ir);
+        let base = match self.base.scatter_mc_method(r_in, rec) {
+            Some(ScatterRecord::Scatter { pdf, .. }) | Some(ScatterRecord::Microfacet { pdf, .. }) => Some(Box::new(pdf)),
+            Some(ScatterRecord::Specular { .. }) => {
+                let (direction, attenuation) = self.sample_through(r_in, rec)?;
+                return Some(ScatterRecord::Specular {
+                    specular_ray: Ray::new(rec.position, direction, r_in.time()),
+                    attenuation
+                })
+            },
+            _ => None
+        };
+        Some(ScatterRecord::Microfacet {
+            pdf: PDF::layered_pdf(rec.normal, direction, self.alpha(), fresnel, base),
+            eta: 1.0
+        })
+    }
+
+    fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        self.bsdf(r_in, r_out, rec, 1.0)
+    }
+
+    fn bsdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord, _eta: f64) -> Vec3 {
+        let wo = r_in.direction().normalized() * (-1.0);
+        let wi = r_out.direction().normalized();
+        let n = rec.normal;
+        if n.dot(wo) <= 0.0 || n.dot(wi) <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0)
+        }
+        let alpha = self.alpha();
+        let eta = 1.0 / self.ir;
+        let mut rng = rand::thread_rng();
+
+        // reflection off the coat itself
+        let reflection = self.coat(wo, wi, n);
+        let mut f = Color::new(reflection, reflection, reflection);
+
+        // through the coat from wo
+        let enter_pdf = PDF::rough_dielectric_pdf(n, (-1.0) * wo, alpha, eta);
+        let mut w = match enter_pdf.generate() {
+            Some(w) => w,
+            None => return f
+        };
+        let value = enter_pdf.value(w);
+        if w.dot(n) >= 0.0 || value <= 0.0 {
+            return f
+        }
+        let mut beta = self.transmittance(w, n) * (self.coat(wo, w, n) * n.dot(w).abs() / value);
+
+        // and through it from wi, by reciprocity the way light toward wi leaves the layer, with f over pdf
+        let exit_pdf = PDF::rough_dielectric_pdf(n, (-1.0) * wi, alpha, eta);
+        let exit = exit_pdf.generate().map(|exit| (exit, exit_pdf.value(exit)))
+            .filter(|&(exit, value)| exit.dot(n) < 0.0 && value > 0.0)
+            .map(|(exit, value)| (exit, self.coat(exit, wi, n) / value));
+
+        for depth in 0..LAYER_DEPTH {
+            if depth > 3 {
+                let max = beta.x().max(beta.y()).max(beta.z());
+                if max < 0.25 {
+                    let q = 1.0 - max;
+                    if rng.gen::<f64>() < q {
+                        break
+                    }
+                    beta /= 1.0 - q;
+                }
+            }
+
+            let base_ray = Ray::new(rec.position, w, r_in.time());
+            let record = match self.base.scatter_mc_method(&base_ray, rec) {
+                Some(record) => record,
+                None => break
+            };
+
+            // connect to the way out toward wi, weighted against sampling the base into it
+            if let Some((exit, exit_weight)) = exit {
+                let up = (-1.0) * exit;
+                let f_cos = record_f_cos(&self.base, &record, &base_ray, rec, up);
+                if !f_cos.near_zero() {
+                    let weight = power_heuristic(exit_pdf.value(exit), Self::base_pdf(&record, up));
+                    f += beta * f_cos * self.transmittance(exit, n) * (exit_weight * weight);
+                }
+            }
+
+            let (next, weight, next_pdf) = match self.sample_base(&record, &base_ray, rec) {
+                Some(sample) => sample,
+                None => break
+            };
+            if next.dot(n) <= 0.0 {
+                break
+            }
+            w = next;
+            beta = beta * weight * self.transmittance(w, n);
+
+            // the base sample leaving through the coat toward wi on its own
+            let f_exit = self.coat((-1.0) * w, wi, n);
+            if f_exit > 0.0 {
+                let weight = match next_pdf {
+                    Some(pdf) => power_heuristic(pdf, exit_pdf.value((-1.0) * w)),
+                    None => 1.0
+                };
+                f += beta * (f_exit * weight);
+            }
+
+            // back down off the underside of the coat, light leaving through it was counted above
+            let pdf = PDF::rough_dielectric_pdf((-1.0) * n, w, alpha, self.ir);
+            let down = match pdf.generate() {
+                Some(down) => down,
+                None => break
+            };
+            let value = pdf.value(down);
+            if down.dot(n) >= 0.0 || value <= 0.0 {
+                break
+            }
+            beta = beta * self.transmittance(down, n) * (self.coat((-1.0) * w, down, n) * n.dot(down).abs() / value);
+            w = down;
+        }
+        f
+    }
+}
+
+// the luminance of a texture averaged over a coarse uv grid, for estimates of a light's power. it is only looked
+// up in uv at the origin, a texture that varies in space instead counts with its value there
+fn uv_average_luminance<T: Texture>(texture: &T) -> f64 {
+    const N: usize = 4;
+    let origin = Vec3::new(0.0, 0.0, 0.0);
+    let total: f64 = (0..N * N).map(|i| {
+        let u = ((i % N) as f64 + 0.5) / N as f64;
+        let v = ((i / N) as f64 + 0.5) / N as f64;
+        texture.mapping(u, v, &origin).luminance()
+    }).sum();
+    total / (N * N) as f64
+}
+
+fn density(record: Option<ScatterRecord>) -> Option<PDF> {
+    match record {
+        Some(ScatterRecord::Scatter { pdf, .. }) | Some(ScatterRecord::Microfacet { pdf, .. }) => Some(pdf),
+        _ => None
+    }
+}
+
+// a record the integrators follow without evaluating it, the material's own if it is already one, otherwise a
+// direction sampled from its density with f cos over pdf. scale multiplies the attenuation
+fn sampled_specular<'a, M: Material + ?Sized>(material: &M, record: ScatterRecord<'a>, r_in: &Ray, rec: &HitRecord, scale: f64) -> Option<ScatterRecord<'a>> {
+    match record {
+        ScatterRecord::Specular { specular_ray, attenuation } => Some(ScatterRecord::Specular { specular_ray, attenuation: scale * attenuation }),
+        ScatterRecord::PassThrough { ray } => Some(ScatterRecord::Specular { specular_ray: ray, attenuation: Color::new(scale, scale, scale) }),
+        ScatterRecord::Scatter { ref pdf, .. } | ScatterRecord::Microfacet { ref pdf, .. } => {
+            let wi = pdf.generate()?.normalized();
+            let value = pdf.value(wi);
+            if value <= 0.0 {
+                return None
+            }
+            Some(ScatterRecord::Specular {
+                specular_ray: Ray::new(rec.position, wi, r_in.time()),
+                attenuation: record_f_cos(material, &record, r_in, rec, wi) * (scale / value)
+            })
+        },
+        _ => None
+    }
+}
+
+// the lobes of two materials added up with the coefficients, sampled from each in proportion to its coefficient.
+// a delta lobe can not be added to a density, so where either material is specular the whole hit point is: one of
+// the two is picked at random and the pick is followed without being evaluated, like a fuzzy metal
+fn combine<'a>(first: (&dyn Material, Option<ScatterRecord<'a>>), second: (&dyn Material, Option<ScatterRecord<'a>>), coefficients: [f64; 2],
+    eta: f64, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord<'a>> {
+    let ((first, first_record), (second, second_record)) = (first, second);
+    let total = coefficients[0] + coefficients[1];
+    if total <= 0.0 {
+        return None
+    }
+    let weight = coefficients[1] / total;
+    let delta = |record: &Option<ScatterRecord>| matches!(record, Some(ScatterRecord::Specular { .. }) | Some(ScatterRecord::PassThrough { .. }));
+    if delta(&first_record) || delta(&second_record) {
+        // picked with probability coefficient over total, so the pick is scaled by the total
+        return if rand::thread_rng().gen::<f64>() < weight {
+            sampled_specular(second, second_record?, r_in, rec, total)
+        } else {
+            sampled_specular(first, first_record?, r_in, rec, total)
+        }
+    }
+    let pdf = match (density(first_record), density(second_record)) {
+        (Some(p0), Some(p1)) => PDF::blend_pdf(p0, p1, weight),
+        (Some(pdf), None) | (None, Some(pdf)) => pdf,
+        (None, None) => return None
+    };
+    // evaluated through bsdf, the sum of the two materials' own
+    Some(ScatterRecord::Microfacet { pdf, eta })
+}
+
+// the eta a record was made with, for the records of materials that scatter on their own
+fn record_eta(record: &Option<ScatterRecord>) -> Option<f64> {
+    match record {
+        Some(ScatterRecord::Microfacet { eta, .. }) => Some(*eta),
+        _ => None
+    }
+}
+
+// one of two materials at every hit point, the weight texture's luminance is the chance of the second. both are
+// evaluated weighted by it, so the mix is the same every time a vertex is evaluated again. a closed dielectric
+// in a mix bounds its volume, two of them can not tell which volume is inside
+#[derive(Clone)]
+pub struct Mix<A: Material, B: Material, T: Texture> {
+    first: A,
+    second: B,
+    weight: T
+}
+
+impl<A: Material, B: Material, T: Texture> Mix<A, B, T> {
+    pub fn new(first: A, second: B, weight: T) -> Mix<A, B, T> {
+        assert!(first.interior().is_none() || second.interior().is_none(), "a mix of two closed dielectrics bounds two volumes");
+        Mix {
+            first,
+            second,
+            weight
+        }
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        self.weight.mapping(rec.u, rec.v, &rec.position).luminance().clamp(0.0, 1.0)
+    }
+}
+
+impl<A: Material, B: Material, T: Texture> Material for Mix<A, B, T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        if rand::thread_rng().gen::<f64>() < self.weight(rec) { self.second.scatter(r_in, rec) } else { self.first.scatter(r_in, rec) }
+    }
+
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let first = self.first.scatter_mc_method(r_in, rec);
+        let second = self.second.scatter_mc_method(r_in, rec);
+        let eta = record_eta(&first).or_else(|| record_eta(&second)).unwrap_or(1.0);
+        let weight = self.weight(rec);
+        combine((&self.first, first), (&self.second, second), [1.0 - weight, weight], eta, r_in, rec)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.emitted(r_in, rec) + weight * self.second.emitted(r_in, rec)
+    }
+
+    fn emitted_luminance(&self) -> f64 {
+        let weight = uv_average_luminance(&self.weight).clamp(0.0, 1.0);
+        (1.0 - weight) * self.first.emitted_luminance() + weight * self.second.emitted_luminance()
+    }
+
+    fn emits_both_sides(&self) -> bool {
+        self.first.emits_both_sides() || self.second.emits_both_sides()
+    }
+
+    fn interior(&self) -> Option<Interior<'_>> {
+        self.first.interior().or_else(|| self.second.interior())
+    }
+
+    fn scatter_interface(&self, r_in: &Ray, rec: &HitRecord, eta: f64) -> Option<ScatterRecord> {
+        let first = self.first.scatter_interface(r_in, rec, eta);
+        let second = self.second.scatter_interface(r_in, rec, eta);
+        let weight = self.weight(rec);
+        combine((&self.first, first), (&self.second, second), [1.0 - weight, weight], eta, r_in, rec)
+    }
+
+    fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.brdf(r_in, r_out, rec) + weight * self.second.brdf(r_in, r_out, rec)
+    }
+
+    fn bsdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord, eta: f64) -> Vec3 {
+        let weight = self.weight(rec);
+        (1.0 - weight) * self.first.bsdf(r_in, r_out, rec, eta) + weight * self.second.bsdf(r_in, r_out, rec, eta)
+    }
+}
+
+// the sum of two materials' lobes, sampled from either half the time
+#[derive(Clone)]
+pub struct Blend<A: Material, B: Material> {
+    first: A,
+    second: B
+}
+
+impl<A: Material, B: Material> Blend<A, B> {
+    pub fn new(first: A, second: B) -> Blend<A, B> {
+        Blend {
+            first,
+            second
+        }
+    }
+}
+
+impl<A: Material, B: Material> Material for Blend<A, B> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let scattered = if rand::thread_rng().gen::<bool>() { self.first.scatter(r_in, rec) } else { self.second.scatter(r_in, rec) };
+        scattered.map(|(attenuation, ray)| (2.0 * attenuation, ray))
+    }
+
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let first = self.first.scatter_mc_method(r_in, rec);
+        let second = self.second.scatter_mc_method(r_in, rec);
+        combine((&self.first, first), (&self.second, second), [1.0, 1.0], 1.0, r_in, rec)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.first.emitted(r_in, rec) + self.second.emitted(r_in, rec)
+    }
+
+    fn emitted_luminance(&self) -> f64 {
+        self.first.emitted_luminance() + self.second.emitted_luminance()
+    }
+
+    fn emits_both_sides(&self) -> bool {
+        self.first.emits_both_sides() || self.second.emits_both_sides()
+    }
+
+    fn brdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord) -> Vec3 {
+        self.bsdf(r_in, r_out, rec, 1.0)
+    }
+
+    fn bsdf(&self, r_in: &Ray, r_out: &Ray, rec: &HitRecord, eta: f64) -> Vec3 {
+        self.first.bsdf(r_in, r_out, rec, eta) + self.second.bsdf(r_in, r_out, rec, eta)
+    }
+}
+
+#[derive(Clone)]
 pub struct DiffuseLight<T: Texture> {
-    emit: T
+    emit: T,
+    // radiance multiplier, keeps the texture in a displayable range
+    scale: f64,
+    two_sided: bool,
+    profile: Option<IesProfile>
 }
 
 impl<T: Texture> DiffuseLight<T> {
     pub fn new(emit: T) -> DiffuseLight<T> {
         DiffuseLight { 
-            emit
+            emit,
+            scale: 1.0,
+            two_sided: false,
+            profile: None
          }
     }
+
+    pub fn scaled(self, scale: f64) -> DiffuseLight<T> {
+        DiffuseLight {
+            scale,
+            ..self
+        }
+    }
+
+    // emit from the back face as well, instead of wrapping the surface in a FlipNormal
+    pub fn two_sided(self) -> DiffuseLight<T> {
+        DiffuseLight {
+            two_sided: true,
+            ..self
+        }
+    }
+
+    // radiance varies with the angle to the surface normal following a photometric profile
+    pub fn with_profile(self, profile: IesProfile) -> DiffuseLight<T> {
+        DiffuseLight {
+            profile: Some(profile),
+            ..self
+        }
+    }
+}
+
+impl DiffuseLight<ConstantTexture> {
+    // black body color at temperature kelvin with the given luminance
+    pub fn blackbody(temperature: f64, luminance: f64) -> DiffuseLight<ConstantTexture> {
+        DiffuseLight::new(ConstantTexture::new(blackbody(temperature))).scaled(luminance)
+    }
 }
 
 impl<T: Texture> Material for DiffuseLight<T> {
@@ -392,31 +1374,301 @@ impl<T: Texture> Material for DiffuseLight<T> {
         None
     }
 
-    fn emitted(&self, rec: &HitRecord) -> Color {
-        if rec.front_face {
-            self.emit.mapping(rec.u, rec.v, &rec.position)
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face || self.two_sided {
+            // the normal faces the incoming ray on either side. the profile is intensity, the projected area
+            // of the emitter shrinks with the cosine so the radiance is divided by it
+            let profile = self.profile.as_ref().map_or(1.0, |profile| {
+                let direction = (-1.0) * r_in.direction().normalized();
+                profile.value(&ONB::build_from_w(&rec.normal), direction) / direction.dot(rec.normal).max(0.01)
+            });
+            self.scale * profile * self.emit.mapping(rec.u, rec.v, &rec.position)
         } else {
             Color::new(0.0, 0.0, 0.0)
         }
     }
+
+    fn emitted_luminance(&self) -> f64 {
+        let profile = self.profile.as_ref().map_or(1.0, |profile| profile.hemisphere_average());
+        self.scale * profile * uv_average_luminance(&self.emit)
+    }
+
+    fn emits_both_sides(&self) -> bool {
+        self.two_sided
+    }
 }
 
-#[derive(Clone, Copy)]
-pub struct Isotropic<T: Texture> {
-    albedo: T
+// scattering inside a participating medium, albedo is the single scattering albedo
+#[derive(Clone)]
+pub struct PhaseMaterial<T: Texture> {
+    albedo: T,
+    phase: PhaseFunction,
+    emission: Option<Arc<dyn Emission>>
 }
 
-impl<T: Texture> Isotropic<T> {
-    pub fn new(albedo: T) -> Isotropic<T> { 
-        Isotropic { 
-            albedo 
-        } 
+impl<T: Texture> PhaseMaterial<T> {
+    pub fn new(albedo: T, phase: PhaseFunction) -> PhaseMaterial<T> {
+        PhaseMaterial {
+            albedo,
+            phase,
+            emission: None
+        }
+    }
+
+    pub fn with_phase(self, phase: PhaseFunction) -> PhaseMaterial<T> {
+        PhaseMaterial {
+            phase,
+            ..self
+        }
+    }
+
+    pub fn with_albedo(self, albedo: T) -> PhaseMaterial<T> {
+        PhaseMaterial {
+            albedo,
+            ..self
+        }
+    }
+
+    pub fn with_emission(self, emission: impl Emission + 'static) -> PhaseMaterial<T> {
+        PhaseMaterial {
+            emission: Some(Arc::new(emission)),
+            ..self
+        }
     }
 }
 
-impl<T: Texture> Material for Isotropic<T> {
+impl<T: Texture> Material for PhaseMaterial<T> {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let scattered = Ray::new(rec.position, Vec3::random_in_unit_sphere(), r_in.time());
+        let scattered = Ray::new(rec.position, self.phase.sample(r_in.direction().normalized()), r_in.time());
         Some((self.albedo.mapping(rec.u, rec.v, &rec.position), scattered))
     }
-}
\ No newline at end of file
+
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord::Volume {
+            pdf: PDF::phase_pdf(self.phase, r_in.direction()),
+            attenuation: self.albedo.mapping(rec.u, rec.v, &rec.position)
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, _rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.phase.value(r_in.direction().normalized(), scattered.direction().normalized())
+    }
+
+    // collisions are found in proportion to the extinction, of which the absorbed part emits
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        match &self.emission {
+            Some(emission) => (Color::new(1.0, 1.0, 1.0) - self.albedo.mapping(rec.u, rec.v, &rec.position)) * emission.emission(rec.position),
+            None => Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+// the end of a stretch of medium crossed without scattering, its weight is in the hit record
+pub struct PassThrough;
+
+impl Material for PassThrough {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        Some((Color::new(1.0, 1.0, 1.0), Ray::new(rec.position, r_in.direction(), r_in.time())))
+    }
+
+    fn scatter_mc_method(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord::PassThrough {
+            ray: Ray::new(rec.position, r_in.direction(), r_in.time())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Point3;
+
+    // midpoint rule over the sphere in theta and phi, the lobes are symmetric about the plane of wo and n
+    fn integrate_sphere(f: impl Fn(Vec3) -> f64) -> f64 {
+        const THETA: usize = 1000;
+        const PHI: usize = 500;
+        let (d_theta, d_phi) = (f64::consts::PI / THETA as f64, f64::consts::PI / PHI as f64);
+        let mut total = 0.0;
+        for i in 0..THETA {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..PHI {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let w = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                total += f(w) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        2.0 * total
+    }
+
+    // a hit at the origin of a surface facing up along z
+    fn hit_record(material: &dyn Material) -> HitRecord<'_> {
+        HitRecord {
+            position: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            weight: Color::new(1.0, 1.0, 1.0),
+            material
+        }
+    }
+
+    // light arriving from the direction wo above the surface, the average of f cos over pdf of the records made for it
+    fn sampled_albedo(material: &dyn Material, wo: Vec3, samples: usize) -> Color {
+        let rec = hit_record(material);
+        let r_in = Ray::new(wo, (-1.0) * wo, 0.0);
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let record = match material.scatter_mc_method(&r_in, &rec) {
+                Some(record) => record,
+                None => continue
+            };
+            total += match &record {
+                ScatterRecord::Specular { attenuation, .. } => *attenuation,
+                ScatterRecord::Scatter { pdf, .. } | ScatterRecord::Microfacet { pdf, .. } => match pdf.generate() {
+                    Some(wi) if pdf.value(wi) > 0.0 => record_f_cos(material, &record, &r_in, &rec, wi.normalized()) / pdf.value(wi),
+                    _ => Color::new(0.0, 0.0, 0.0)
+                },
+                _ => Color::new(0.0, 0.0, 0.0)
+            };
+        }
+        total / samples as f64
+    }
+
+    // outgoing directions from straight above to grazing, from air into glass and from glass out into air
+    fn rough_dielectric_cases() -> Vec<(f64, f64, Vec3)> {
+        let mut cases = Vec::new();
+        for alpha in [0.05, 0.2, 0.6] {
+            for eta in [1.0 / 1.5, 1.5] {
+                for cos_o in [1.0, 0.7, 0.3] {
+                    let sin_o: f64 = 1.0 - cos_o * cos_o;
+                    cases.push((alpha, eta, Vec3::new(sin_o.sqrt(), 0.0, cos_o)));
+                }
+            }
+        }
+        cases
+    }
+
+    #[test]
+    fn rough_dielectric_pdf_integrates_to_one() {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        for (alpha, eta, wo) in rough_dielectric_cases() {
+            let pdf = PDF::rough_dielectric_pdf(n, (-1.0) * wo, alpha, eta);
+            let total = integrate_sphere(|wi| pdf.value(wi));
+            // steep microfacets send some samples to the wrong side, generate gives those up
+            const SAMPLES: usize = 100000;
+            let given_up = (0..SAMPLES).filter(|_| pdf.generate().is_none()).count() as f64 / SAMPLES as f64;
+            assert!((total + given_up - 1.0).abs() < 0.01, "pdf integrates to {} with {} given up for alpha {}, eta {}, cos_o {}", total, given_up, alpha, eta, wo.z());
+            if alpha <= 0.05 {
+                assert!(total > 0.99, "pdf integrates to {} for alpha {}, eta {}, cos_o {}", total, alpha, eta, wo.z());
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_presets_reflect_their_metals_colors() {
+        // measured reflectance at normal incidence, in the same three channels as the indices of refraction
+        let presets = [
+            ("gold", Conductor::gold(0.0), Color::new(1.000, 0.766, 0.336)),
+            ("copper", Conductor::copper(0.0), Color::new(0.955, 0.638, 0.538)),
+            ("silver", Conductor::silver(0.0), Color::new(0.972, 0.960, 0.915)),
+            ("aluminium", Conductor::aluminium(0.0), Color::new(0.913, 0.922, 0.924))
+        ];
+        for (name, conductor, expected) in presets {
+            let f0 = conductor.fresnel(1.0);
+            let grazing = conductor.fresnel(0.0);
+            for i in 0..3 {
+                let (eta, k) = (conductor.eta[i], conductor.k[i]);
+                let exact = ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+                assert!((f0[i] - exact).abs() < 1e-9, "{} reflects {} at normal incidence instead of {} in channel {}", name, f0[i], exact, i);
+                assert!((f0[i] - expected[i]).abs() < 0.05, "{} reflects {} instead of {} in channel {}", name, f0[i], expected[i], i);
+                assert!((grazing[i] - 1.0).abs() < 1e-9, "{} reflects {} at grazing angles in channel {}", name, grazing[i], i);
+            }
+        }
+    }
+
+    #[test]
+    fn conductors_conserve_energy() {
+        for roughness in [0.2, 0.5, 0.9] {
+            let conductor = Conductor::gold(roughness);
+            let rec = hit_record(&conductor);
+            for cos_o in [1.0, 0.7, 0.3] {
+                let sin_o: f64 = 1.0 - cos_o * cos_o;
+                let wo = Vec3::new(sin_o.sqrt(), 0.0, cos_o);
+                let r_in = Ray::new(wo, (-1.0) * wo, 0.0);
+                let albedo = integrate_sphere(|wi| conductor.brdf(&r_in, &Ray::new(rec.position, wi, 0.0), &rec)[0] * wi.z().max(0.0));
+                // the red channel reflects nearly everything, a single scattering model only loses what bounces between microfacets
+                assert!(albedo <= 1.0, "albedo {} for roughness {}, cos_o {}", albedo, roughness, cos_o);
+                if roughness <= 0.2 {
+                    assert!(albedo > 0.9, "albedo {} for roughness {}, cos_o {}", albedo, roughness, cos_o);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn coated_layers_conserve_energy() {
+        let white = Lambertian::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0)));
+        for cos_o in [1.0, 0.5] {
+            let sin_o: f64 = 1.0 - cos_o * cos_o;
+            let wo = Vec3::new(sin_o.sqrt(), 0.0, cos_o);
+            // a white base under a clear coat keeps all but what the walk leaves inside and the rough coat loses
+            for roughness in [0.0, 0.3] {
+                let albedo = sampled_albedo(&Coated::new(white, 1.5, roughness), wo, 100000).x();
+                assert!(albedo <= 1.02 && albedo > 0.85, "albedo {} for roughness {}, cos_o {}", albedo, roughness, cos_o);
+            }
+            // over a mirror the walk is all there is, and glass below lets through what the coat does not reflect
+            let albedo = sampled_albedo(&Coated::new(Conductor::silver(0.0), 1.5, 0.2), wo, 100000);
+            assert!(albedo.x() <= 1.02 && albedo.x() > 0.9, "albedo {} over silver for cos_o {}", albedo.x(), cos_o);
+            let albedo = sampled_albedo(&Coated::new(Dielectric::new(1.5), 1.5, 0.2), wo, 100000).x();
+            assert!((albedo - 1.0).abs() < 0.02, "albedo {} over glass for cos_o {}", albedo, cos_o);
+        }
+    }
+
+    #[test]
+    fn mixes_sample_what_they_evaluate() {
+        let gray = || Lambertian::new(ConstantTexture::new(Color::new(0.5, 0.5, 0.5)));
+        let weight = ConstantTexture::new(Color::new(0.3, 0.3, 0.3));
+        let cos_o: f64 = 0.7;
+        let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+        let r_in = Ray::new(wo, (-1.0) * wo, 0.0);
+
+        // glossy lobes are evaluated as the weighted sum, and sampled in proportion to it
+        let materials: [(&str, &dyn Material); 2] = [
+            ("mix", &Mix::new(gray(), Conductor::gold(0.4), weight)),
+            ("blend", &Blend::new(gray(), Conductor::gold(0.4)))
+        ];
+        for (name, material) in materials {
+            let rec = hit_record(material);
+            let evaluated = integrate_sphere(|wi| material.bsdf(&r_in, &Ray::new(rec.position, wi, 0.0), &rec, 1.0).x() * wi.z().max(0.0));
+            let sampled = sampled_albedo(material, wo, 200000).x();
+            assert!((sampled - evaluated).abs() < 0.01, "{} samples an albedo of {} and evaluates {}", name, sampled, evaluated);
+        }
+        let mix = Mix::new(gray(), Conductor::gold(0.4), weight);
+        let rec = hit_record(&mix);
+        let r_out = Ray::new(rec.position, Vec3::new(-0.3, 0.2, 0.9).normalized(), 0.0);
+        let expected = 0.7 * gray().bsdf(&r_in, &r_out, &rec, 1.0) + 0.3 * Conductor::gold(0.4).bsdf(&r_in, &r_out, &rec, 1.0);
+        assert!((mix.bsdf(&r_in, &r_out, &rec, 1.0) - expected).length() < 1e-12);
+
+        // over a mirror the pick is weighted by its chance
+        let mirror = Conductor::silver(0.0).fresnel(cos_o).x();
+        let sampled = sampled_albedo(&Mix::new(Conductor::silver(0.0), gray(), weight), wo, 200000).x();
+        assert!((sampled - (0.7 * mirror + 0.3 * 0.5)).abs() < 0.01, "mix over a mirror reflects {}", sampled);
+        let sampled = sampled_albedo(&Blend::new(gray(), Conductor::silver(0.0)), wo, 200000).x();
+        assert!((sampled - (0.5 + mirror)).abs() < 0.02, "blend over a mirror reflects {}", sampled);
+    }
+
+    #[test]
+    fn rough_dielectric_conserves_energy() {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        for (alpha, eta, wo) in rough_dielectric_cases() {
+            // reflected and transmitted, a single scattering model loses what bounces between microfacets
+            let albedo = integrate_sphere(|wi| rough_dielectric_bsdf(wo, wi, n, eta, alpha) * wi.z().abs());
+            assert!(albedo <= 1.01, "albedo {} for alpha {}, eta {}, cos_o {}", albedo, alpha, eta, wo.z());
+            if alpha <= 0.05 {
+                assert!(albedo > 0.97, "albedo {} for alpha {}, eta {}, cos_o {}", albedo, alpha, eta, wo.z());
+            }
+        }
+    }
+}
@@ -1,16 +1,60 @@
 use::std::f64;
 use rand::Rng;
-use super::vec::Vec3;
+use super::vec::{Vec3, Point3, Color};
 use super::ray::Ray;
 use super::hit::{Hittable,HitRecord};
-use super::mat::Isotropic;
-use super::texture::Texture;
+use super::mat::{Material, PhaseMaterial, PassThrough};
+use super::phase::PhaseFunction;
+use super::texture::{Texture, ConstantTexture};
 use super::aabb::AABB;
+use super::spectrum::{blackbody, blackbody_luminance};
+
+// calls inside for each part of the ray between t_min and t_max within a closed boundary, until it returns something
+fn inside_boundary<H: Hittable, R>(boundary: &H, r: &Ray, t_min: f64, t_max: f64, mut inside: impl FnMut(f64, f64) -> Option<R>) -> Option<R> {
+    // boundary hits along the whole line come in entering and leaving pairs
+    let mut t = -f64::MAX;
+    while let Some(enter) = boundary.hit(r, t, f64::MAX) {
+        let exit = boundary.hit(r, enter.t + 0.0001, f64::MAX)?;
+        let (t0, t1) = (enter.t.max(t_min), exit.t.min(t_max));
+        if t0 < t1 {
+            if let Some(result) = inside(t0, t1) {
+                return Some(result)
+            }
+        }
+        if exit.t >= t_max {
+            break
+        }
+        t = exit.t + 0.0001;
+    }
+    None
+}
+
+fn medium_record<'a>(r: &Ray, t: f64, material: &'a dyn Material, weight: Color) -> HitRecord<'a> {
+    HitRecord {
+        position: r.at(t),
+        u: 0.0,
+        v: 0.0,
+        t,
+        front_face: false, // arbitrary
+        weight,
+        normal: Vec3::new(1.0, 0.0, 0.0), // arbitrary
+        material
+    }
+}
+
+// fraction of each channel left after distance through a constant coefficient
+fn beer_lambert(coefficient: Color, distance: f64) -> Color {
+    Color::new((-coefficient.x() * distance).exp(), (-coefficient.y() * distance).exp(), (-coefficient.z() * distance).exp())
+}
+
+fn average(color: Color) -> f64 {
+    (color.x() + color.y() + color.z()) / 3.0
+}
 
 pub struct ConstantMedium<H: Hittable, T: Texture> {
     boundary: H,
     density: f64,
-    phase_function: Isotropic<T>
+    phase_function: PhaseMaterial<T>
 }
 
 impl<H: Hittable, T: Texture> ConstantMedium<H, T> {
@@ -18,7 +62,23 @@ impl<H: Hittable, T: Texture> ConstantMedium<H, T> {
         ConstantMedium {
             boundary,
             density,
-            phase_function: Isotropic::new(texture)
+            phase_function: PhaseMaterial::new(texture, PhaseFunction::Isotropic)
+        }
+    }
+
+    // scatters uniformly unless given another phase function
+    pub fn with_phase(self, phase: PhaseFunction) -> ConstantMedium<H, T> {
+        ConstantMedium {
+            phase_function: self.phase_function.with_phase(phase),
+            ..self
+        }
+    }
+
+    // glows with the emission's radiance wherever it absorbs
+    pub fn with_emission(self, emission: impl Emission + 'static) -> ConstantMedium<H, T> {
+        ConstantMedium {
+            phase_function: self.phase_function.with_emission(emission),
+            ..self
         }
     }
 }
@@ -26,41 +86,454 @@ impl<H: Hittable, T: Texture> ConstantMedium<H, T> {
 impl<H: Hittable, T: Texture> Hittable for ConstantMedium<H, T> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let mut rng = rand::thread_rng();
-        if let Some(mut hit1) = self.boundary.hit(r, -f64::MAX, f64::MAX) {
-            if let Some(mut hit2) = self.boundary.hit(r, hit1.t + 0.0001, f64::MAX) {
+        let length = r.direction().length();
+        inside_boundary(&self.boundary, r, t_min, t_max, |t0, t1| {
+            let distance_inside_boundary = (t1 - t0) * length;
+            let hit_distance = -(1.0 / self.density) * rng.gen::<f64>().ln();
+            if hit_distance < distance_inside_boundary {
+                Some(t0 + hit_distance / length)
+            } else {
+                None
+            }
+        }).map(|t| medium_record(r, t, &self.phase_function, Color::new(1.0, 1.0, 1.0)))
+    }
 
-                if hit1.t < t_min {
-                    hit1.t = t_min
-                }
-                
-                if hit2.t > t_max {
-                    hit2.t = t_max
-                }
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        let length = r.direction().length();
+        let mut distance = 0.0;
+        inside_boundary(&self.boundary, r, t_min, t_max, |t0, t1| -> Option<()> {
+            distance += (t1 - t0) * length;
+            None
+        });
+        let transmittance = (-self.density * distance).exp();
+        Color::new(transmittance, transmittance, transmittance)
+    }
 
-                if hit1.t < hit2.t {
-                    let distance_inside_boundary = (hit2.t - hit1.t) * r.direction().length();
-                    let hit_distance = -(1.0 / self.density) * rng.gen::<f64>().ln();
-                    if hit_distance < distance_inside_boundary {
-                        let t = hit1.t + hit_distance / r.direction().length();
-                        return Some(
-                            HitRecord {
-                                position: r.at(t),
-                                u: 0.0,
-                                v: 0.0,
-                                t,
-                                front_face: false, // arbitrary
-                                normal: Vec3::new(1.0, 0.0, 0.0), // arbitrary
-                                material: &self.phase_function
-                            }
-                        )
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
+
+// a medium that fills the inside of a dielectric, which tells it where it ends
+pub trait Medium: Send + Sync {
+    // scattering along r between t_min and t_max, else crossing that stretch with the weight of getting through
+    fn sample(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color;
+}
+
+// absorbs and scatters every channel differently, like wine or milk. free flights follow the scattering of one
+// channel picked at random and are weighted against all three, absorption only ever weights the path. media that
+// glow follow the extinction instead, so that the absorbed part of every collision can emit
+pub struct HomogeneousMedium {
+    absorption: Color,
+    scattering: Color,
+    // coefficients the free flights are drawn with
+    sampling: Color,
+    phase_function: PhaseMaterial<ConstantTexture>
+}
+
+// share of the collisions drawn with sampling that scatter, per channel
+fn collision_albedo(scattering: Color, sampling: Color) -> ConstantTexture {
+    let channel = |i: usize| if sampling[i] > 0.0 { scattering[i] / sampling[i] } else { 0.0 };
+    ConstantTexture::new(Color::new(channel(0), channel(1), channel(2)))
+}
+
+impl HomogeneousMedium {
+    // coefficients per unit of distance
+    pub fn new(absorption: Color, scattering: Color) -> HomogeneousMedium {
+        HomogeneousMedium {
+            absorption,
+            scattering,
+            sampling: scattering,
+            // the scattering coefficients already are in the weights
+            phase_function: PhaseMaterial::new(collision_albedo(scattering, scattering), PhaseFunction::Isotropic)
+        }
+    }
+
+    // scatters uniformly unless given another phase function
+    pub fn with_phase(self, phase: PhaseFunction) -> HomogeneousMedium {
+        HomogeneousMedium {
+            phase_function: self.phase_function.with_phase(phase),
+            ..self
+        }
+    }
+
+    // glows with the emission's radiance wherever it absorbs, a colored absorption tints the glow it lets through
+    pub fn with_emission(self, emission: impl Emission + 'static) -> HomogeneousMedium {
+        let extinction = self.absorption + self.scattering;
+        HomogeneousMedium {
+            sampling: extinction,
+            phase_function: self.phase_function.with_albedo(collision_albedo(self.scattering, extinction)).with_emission(emission),
+            ..self
+        }
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn sample(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rng = rand::thread_rng();
+        let length = r.direction().length();
+        // stops just short of the end, so whatever surface is there is still hit by the ray going on
+        let t_max = t_max - 0.0001 / length;
+        if t_max <= t_min {
+            return None
+        }
+        let distance = (t_max - t_min) * length;
+        let extinction = self.absorption + self.scattering;
+        let hit_distance = -(1.0 - rng.gen::<f64>()).ln() / self.sampling[rng.gen_range(0..3)];
+        if hit_distance < distance {
+            // the pdf of the distance is the average of the three channels' pdfs
+            let pdf = average(self.sampling * beer_lambert(self.sampling, hit_distance));
+            let weight = beer_lambert(extinction, hit_distance) * self.sampling / pdf;
+            Some(medium_record(r, t_min + hit_distance / length, &self.phase_function, weight))
+        } else {
+            let probability = average(beer_lambert(self.sampling, distance));
+            let weight = beer_lambert(extinction, distance) / probability;
+            Some(medium_record(r, t_max, &PassThrough, weight))
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        beer_lambert(self.absorption + self.scattering, (t_max - t_min) * r.direction().length())
+    }
+}
+
+// a homogeneous medium within a closed boundary of its own
+pub struct ChromaticMedium<H: Hittable> {
+    boundary: H,
+    medium: HomogeneousMedium
+}
+
+impl<H: Hittable> ChromaticMedium<H> {
+    pub fn new(boundary: H, absorption: Color, scattering: Color) -> ChromaticMedium<H> {
+        ChromaticMedium {
+            boundary,
+            medium: HomogeneousMedium::new(absorption, scattering)
+        }
+    }
+
+    pub fn with_phase(self, phase: PhaseFunction) -> ChromaticMedium<H> {
+        ChromaticMedium {
+            medium: self.medium.with_phase(phase),
+            ..self
+        }
+    }
+
+    pub fn with_emission(self, emission: impl Emission + 'static) -> ChromaticMedium<H> {
+        ChromaticMedium {
+            medium: self.medium.with_emission(emission),
+            ..self
+        }
+    }
+}
+
+impl<H: Hittable> Hittable for ChromaticMedium<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        inside_boundary(&self.boundary, r, t_min, t_max, |t0, t1| self.medium.sample(r, t0, t1))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        let mut transmittance = Color::new(1.0, 1.0, 1.0);
+        inside_boundary(&self.boundary, r, t_min, t_max, |t0, t1| -> Option<()> {
+            transmittance = transmittance * self.medium.transmittance(r, t0, t1);
+            None
+        });
+        transmittance
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
+
+// density at every point of a heterogeneous medium
+pub trait Density: Sync {
+    fn density(&self, p: Point3) -> f64;
+    // no point may be denser, the tracking samples against it
+    fn max_density(&self) -> f64;
+    // pieces of r between t_min and t_max with a bound on the density in each, visit returns true to stop
+    fn majorants(&self, _r: &Ray, t_min: f64, t_max: f64, visit: &mut dyn FnMut(f64, f64, f64) -> bool) {
+        visit(t_min, t_max, self.max_density());
+    }
+}
+
+// a texture's luminance in [0, 1] scaled up to max_density
+pub struct TextureDensity<T: Texture> {
+    texture: T,
+    max_density: f64
+}
+
+impl<T: Texture> TextureDensity<T> {
+    pub fn new(texture: T, max_density: f64) -> TextureDensity<T> {
+        TextureDensity {
+            texture,
+            max_density
+        }
+    }
+}
+
+impl<T: Texture> Density for TextureDensity<T> {
+    fn density(&self, p: Point3) -> f64 {
+        self.max_density * self.texture.mapping(0.0, 0.0, &p).luminance().clamp(0.0, 1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_density
+    }
+}
+
+// radiance a medium gives off at every point, seen where the medium absorbs
+pub trait Emission: Send + Sync {
+    fn emission(&self, p: Point3) -> Color;
+}
+
+// a field in [0, 1], like a density grid, tinting a single color
+pub struct EmissionField<D: Density> {
+    field: D,
+    color: Color
+}
+
+impl<D: Density + Send> EmissionField<D> {
+    pub fn new(field: D, color: Color) -> EmissionField<D> {
+        EmissionField {
+            field,
+            color
+        }
+    }
+}
+
+impl<D: Density + Send> Emission for EmissionField<D> {
+    fn emission(&self, p: Point3) -> Color {
+        self.field.density(p) * self.color
+    }
+}
+
+// steps between zero and the hottest temperature, looked up instead of integrating planck's law at every collision
+const BLACKBODY_STEPS: usize = 256;
+
+// glowing like a black body at a temperature field in kelvin, brighter and bluer as it gets hotter
+pub struct BlackbodyEmission<D: Density> {
+    temperature: D,
+    max_temperature: f64,
+    table: Vec<Color>
+}
+
+impl<D: Density> BlackbodyEmission<D> {
+    // the hottest point the field can reach has the given luminance
+    pub fn new(temperature: D, luminance: f64) -> BlackbodyEmission<D> {
+        let max_temperature = temperature.max_density();
+        let max_luminance = blackbody_luminance(max_temperature);
+        let table = (0..BLACKBODY_STEPS).map(|i| {
+            let t = max_temperature * i as f64 / (BLACKBODY_STEPS - 1) as f64;
+            // planck's law underflows for cold bodies, which give off no visible light anyway
+            let relative = if max_luminance > 0.0 { blackbody_luminance(t) / max_luminance } else { 0.0 };
+            if relative > 0.0 { luminance * relative * blackbody(t) } else { Color::new(0.0, 0.0, 0.0) }
+        }).collect();
+        BlackbodyEmission {
+            temperature,
+            max_temperature,
+            table
+        }
+    }
+}
+
+impl<D: Density + Send> Emission for BlackbodyEmission<D> {
+    fn emission(&self, p: Point3) -> Color {
+        if self.max_temperature <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0)
+        }
+        let x = (self.temperature.density(p) / self.max_temperature).clamp(0.0, 1.0) * (BLACKBODY_STEPS - 1) as f64;
+        let i = (x as usize).min(BLACKBODY_STEPS - 2);
+        let fraction = x - i as f64;
+        (1.0 - fraction) * self.table[i] + fraction * self.table[i + 1]
+    }
+}
+
+// a medium whose density varies in space, scattering is found by delta tracking and shadow rays use ratio tracking
+pub struct HeterogeneousMedium<H: Hittable, D: Density, T: Texture> {
+    boundary: H,
+    density: D,
+    phase_function: PhaseMaterial<T>
+}
+
+impl<H: Hittable, D: Density, T: Texture> HeterogeneousMedium<H, D, T> {
+    pub fn new(boundary: H, density: D, texture: T) -> HeterogeneousMedium<H, D, T> {
+        HeterogeneousMedium {
+            boundary,
+            density,
+            phase_function: PhaseMaterial::new(texture, PhaseFunction::Isotropic)
+        }
+    }
+
+    // scatters uniformly unless given another phase function
+    pub fn with_phase(self, phase: PhaseFunction) -> HeterogeneousMedium<H, D, T> {
+        HeterogeneousMedium {
+            phase_function: self.phase_function.with_phase(phase),
+            ..self
+        }
+    }
+
+    // glows with the emission's radiance wherever it absorbs, like fire in its smoke
+    pub fn with_emission(self, emission: impl Emission + 'static) -> HeterogeneousMedium<H, D, T> {
+        HeterogeneousMedium {
+            phase_function: self.phase_function.with_emission(emission),
+            ..self
+        }
+    }
+
+    // tentative collisions against the majorants with the majorant at each, visit returns something to stop
+    fn track<R>(&self, r: &Ray, t_min: f64, t_max: f64, mut visit: impl FnMut(f64, f64) -> Option<R>) -> Option<R> {
+        if self.density.max_density() <= 0.0 {
+            return None
+        }
+        let mut rng = rand::thread_rng();
+        let length = r.direction().length();
+        inside_boundary(&self.boundary, r, t_min, t_max, |t0, t1| {
+            let mut result = None;
+            // flights are memoryless, so every piece starts over at its own majorant
+            self.density.majorants(r, t0, t1, &mut |start, end, majorant| {
+                if majorant <= 0.0 {
+                    return false
+                }
+                let mut t = start;
+                loop {
+                    t -= (1.0 - rng.gen::<f64>()).ln() / (majorant * length);
+                    if t >= end {
+                        return false
+                    }
+                    if let Some(found) = visit(t, majorant) {
+                        result = Some(found);
+                        return true
                     }
                 }
-            }
-        }
-        None
+            });
+            result
+        })
+    }
+}
+
+impl<H: Hittable, D: Density, T: Texture> Hittable for HeterogeneousMedium<H, D, T> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rng = rand::thread_rng();
+        // a tentative collision is real with the ratio of the density to its bound
+        self.track(r, t_min, t_max, |t, majorant| {
+            if rng.gen::<f64>() * majorant < self.density.density(r.at(t)) { Some(t) } else { None }
+        }).map(|t| medium_record(r, t, &self.phase_function, Color::new(1.0, 1.0, 1.0)))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        let mut transmittance = 1.0;
+        self.track(r, t_min, t_max, |t, majorant| {
+            transmittance *= 1.0 - self.density.density(r.at(t)) / majorant;
+            if transmittance > 0.0 { None } else { Some(()) }
+        });
+        let transmittance = transmittance.max(0.0);
+        Color::new(transmittance, transmittance, transmittance)
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.boundary.bounding_box(t0, t1)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::mat::Lambertian;
+
+    fn expected(absorption: Color, scattering: Color, distance: f64) -> (Color, Color) {
+        let extinction = absorption + scattering;
+        let through = beer_lambert(extinction, distance);
+        let channel = |i: usize| scattering[i] / extinction[i] * (1.0 - through[i]);
+        (Color::new(channel(0), channel(1), channel(2)), through)
+    }
+
+    #[test]
+    fn free_flights_weight_every_channel_without_bias() {
+        // every channel absorbs and scatters differently, the blue one hardly scatters at all
+        let absorption = Color::new(0.1, 0.5, 1.0);
+        let scattering = Color::new(0.8, 0.2, 0.05);
+        let medium = HomogeneousMedium::new(absorption, scattering);
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.5), 0.0);
+        // the medium stops short of the end of the segment
+        let (t_min, t_max) = (0.0, 4.0);
+        let end = t_max - 0.0001 / 0.5;
+
+        const SAMPLES: usize = 400000;
+        let mut scattered = Color::new(0.0, 0.0, 0.0);
+        let mut through = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..SAMPLES {
+            let rec = medium.sample(&r, t_min, t_max).unwrap();
+            if rec.t < end {
+                scattered += rec.weight;
+            } else {
+                through += rec.weight;
+            }
+        }
+
+        // the weights estimate the light scattered once along the segment and the light getting through it
+        let (expected_scattered, expected_through) = expected(absorption, scattering, (end - t_min) * 0.5);
+        for i in 0..3 {
+            let scattered = scattered[i] / SAMPLES as f64;
+            let through = through[i] / SAMPLES as f64;
+            assert!((scattered - expected_scattered[i]).abs() < 0.02 * expected_scattered[i], "channel {} scatters {} instead of {}", i, scattered, expected_scattered[i]);
+            assert!((through - expected_through[i]).abs() < 0.02 * expected_through[i], "channel {} lets {} through instead of {}", i, through, expected_through[i]);
+        }
+        assert!((medium.transmittance(&r, t_min, end)[2] - expected_through[2]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn glowing_media_give_off_what_they_absorb() {
+        let absorption = Color::new(0.1, 0.5, 1.0);
+        let scattering = Color::new(0.8, 0.2, 0.05);
+        let luminance = Color::new(2.0, 2.0, 2.0);
+        let medium = HomogeneousMedium::new(absorption, scattering)
+            .with_emission(EmissionField::new(TextureDensity::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0)), 1.0), luminance));
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.5), 0.0);
+        let (t_min, t_max) = (0.0, 4.0);
+        let end = t_max - 0.0001 / 0.5;
+
+        const SAMPLES: usize = 400000;
+        let mut emitted = Color::new(0.0, 0.0, 0.0);
+        let mut scattered = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..SAMPLES {
+            let rec = medium.sample(&r, t_min, t_max).unwrap();
+            if rec.t < end {
+                emitted += rec.weight * rec.material.emitted(&r, &rec);
+                scattered += rec.weight * rec.material.scatter(&r, &rec).unwrap().0;
+            }
+        }
+
+        // the light absorbed along the segment comes back as the emission, and scattering is unchanged by the glow
+        let extinction = absorption + scattering;
+        let through = beer_lambert(extinction, (end - t_min) * 0.5);
+        let (expected_scattered, _) = expected(absorption, scattering, (end - t_min) * 0.5);
+        for i in 0..3 {
+            let expected_emitted = absorption[i] / extinction[i] * (1.0 - through[i]) * luminance[i];
+            let emitted = emitted[i] / SAMPLES as f64;
+            let scattered = scattered[i] / SAMPLES as f64;
+            assert!((emitted - expected_emitted).abs() < 0.02 * expected_emitted, "channel {} emits {} instead of {}", i, emitted, expected_emitted);
+            assert!((scattered - expected_scattered[i]).abs() < 0.02 * expected_scattered[i], "channel {} scatters {} instead of {}", i, scattered, expected_scattered[i]);
+        }
+    }
+
+    #[test]
+    fn chromatic_media_attenuate_inside_their_boundary() {
+        let boundary = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, Lambertian::new(ConstantTexture::new(Color::new(0.5, 0.5, 0.5))));
+        let absorption = Color::new(0.1, 0.5, 1.0);
+        let scattering = Color::new(0.8, 0.2, 0.05);
+        let medium = ChromaticMedium::new(boundary, absorption, scattering);
+
+        // along a diameter the ray spends a distance of 2 inside, and passes by outside the sphere untouched
+        let r = Ray::new(Point3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let transmittance = medium.transmittance(&r, 0.0, f64::INFINITY);
+        let expected = beer_lambert(absorption + scattering, 2.0);
+        for i in 0..3 {
+            assert!((transmittance[i] - expected[i]).abs() < 1e-3);
+        }
+        let r = Ray::new(Point3::new(-3.0, 1.5, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(medium.hit(&r, 0.0, f64::INFINITY).is_none());
+        assert_eq!(medium.transmittance(&r, 0.0, f64::INFINITY)[0], 1.0);
+    }
+}
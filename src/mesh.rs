@@ -1,36 +1,106 @@
 use std::f64;
 use std::path::Path;
+use rand::Rng;
 use tobj;
 use super::vec::{Vec3, Point3};
-use super::hit::{Hittable, HitRecord, HittableList};
+use super::hit::{Hittable, HitRecord, HittableList, SurfaceSample};
 use super::mat::Material;
 use super::aabb::AABB;
+use super::ray::Ray;
+use super::bvh::BVH;
 use super::tri::Triangle;
+use super::subdivision::{self, Subdivision};
+use super::distribution::Distribution1D;
+use super::light_bvh::LightBounds;
+
+// polygon faces of an obj mesh, face_arities is empty for pure triangle meshes
+fn obj_faces(mesh: &tobj::Mesh) -> Vec<Vec<usize>> {
+    if mesh.face_arities.is_empty() {
+        mesh.indices.chunks(3).map(|f| f.iter().map(|&i| i as usize).collect()).collect()
+    } else {
+        let mut start = 0;
+        mesh.face_arities.iter().map(|&arity| {
+            let face = mesh.indices[start..start + arity as usize].iter().map(|&i| i as usize).collect();
+            start += arity as usize;
+            face
+        }).collect()
+    }
+}
+
+// a triangle with its chance of being picked for light sampling, so the mesh BVH can sum the pdfs along a direction
+struct Weighted {
+    tri: Box<dyn Hittable>,
+    weight: f64
+}
+
+impl Hittable for Weighted {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.tri.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.tri.bounding_box(t0, t1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        self.weight * self.tri.pdf_value(o, v)
+    }
+}
 
 pub struct Mesh {
     // sharing data to calcualte BVH
-    pub tris: HittableList
+    pub tris: HittableList,
+    // area weighted triangle selection for light sampling
+    areas: Distribution1D,
+    // the triangles again with their selection probability, finds the ones a light sampling direction crosses
+    weighted: Option<BVH>
 }
 
 impl Mesh {
-    pub fn new<M: Material + Copy + Clone + 'static>(positions: Vec<Vec3>, indices: Vec<u32>, material: M) -> Mesh {
+    fn from_triangles<M: Material + Clone + 'static>(triangles: Vec<Triangle<M>>) -> Mesh {
+        let areas = Distribution1D::new(triangles.iter().map(|tri| tri.area()).collect());
+        let weighted: Vec<Box<dyn Hittable>> = triangles.iter().enumerate().map(|(i, tri)| {
+            Box::new(Weighted { tri: Box::new(tri.clone()), weight: areas.discrete_pdf(i) }) as Box<dyn Hittable>
+        }).collect();
+
         let mut tris = HittableList::default();
+        for tri in triangles {
+            tris.push(tri);
+        }
 
-        for i in 0..indices.len() / 3 {
+        Mesh {
+            tris,
+            areas,
+            weighted: (!weighted.is_empty()).then(|| BVH::new(weighted, 0.0, 1.0))
+        }
+    }
+
+    pub fn new<M: Material + Clone + 'static>(positions: Vec<Vec3>, indices: Vec<u32>, material: M) -> Mesh {
+        let triangles = (0..indices.len() / 3).map(|i| {
             let vertices = [
                 positions[indices[i * 3] as usize],
                 positions[indices[i * 3 + 1] as usize],
                 positions[indices[i * 3 + 2] as usize],
             ];
-            tris.push(Triangle::new(vertices, material));
-        }
+            Triangle::new(vertices, material.clone())
+        }).collect();
 
-        Mesh {
-            tris
-        }
+        Mesh::from_triangles(triangles)
+    }
+
+    // smooth the polygon mesh with a subdivision surface before building triangles
+    pub fn subdivided<M: Material + Clone + 'static>(positions: Vec<Vec3>, faces: Vec<Vec<usize>>, scheme: Subdivision, material: M) -> Mesh {
+        let (positions, normals, triangles) = subdivision::subdivide(positions, faces, scheme);
+        let triangles = triangles.into_iter().map(|tri| {
+            let vertices = [positions[tri[0]], positions[tri[1]], positions[tri[2]]];
+            let tri_normals = [normals[tri[0]], normals[tri[1]], normals[tri[2]]];
+            Triangle::with_normals(vertices, tri_normals, material.clone())
+        }).collect();
+
+        Mesh::from_triangles(triangles)
     }
 
-    pub fn load_obj<'a, P: AsRef<Path>, M: Material + Copy + Clone + 'static>(
+    pub fn load_obj<'a, P: AsRef<Path>, M: Material + Clone + 'static>(
         path: P,
         offset: Vec3,
         scale: f64,
@@ -51,23 +121,133 @@ impl Mesh {
                     .map(|p| Point3::new(p[0] as f64, p[1] as f64, p[2] as f64) * scale + offset)
                     .collect();
 
-                let tri_indices = &mesh.indices;
+                // fan triangulate polygon faces
+                let tri_indices = obj_faces(mesh)
+                    .iter()
+                    .flat_map(|f| (1..f.len().saturating_sub(1)).flat_map(move |i| [f[0] as u32, f[i] as u32, f[i + 1] as u32]))
+                    .collect();
 
-                Mesh::new(tri_positions, tri_indices.to_vec(), material)
+                Mesh::new(tri_positions, tri_indices, material)
             },
             Err(err) => return Err(format!("Failed to load obj file: {}", err)),
         };
         Ok(models)
     }
 
+    pub fn load_obj_subdivided<P: AsRef<Path>, M: Material + Clone + 'static>(
+        path: P,
+        offset: Vec3,
+        scale: f64,
+        scheme: Subdivision,
+        material: M
+        ) -> Result<Mesh, String> {
+
+        match tobj::load_obj(path.as_ref(), &tobj::OFFLINE_RENDERING_LOAD_OPTIONS) {
+            Ok((models, _)) => {
+                let mesh = &models[0].mesh;
+
+                let positions = mesh
+                    .positions
+                    .chunks(3)
+                    .map(|p| Point3::new(p[0] as f64, p[1] as f64, p[2] as f64) * scale + offset)
+                    .collect();
+
+                Ok(Mesh::subdivided(positions, obj_faces(mesh), scheme, material))
+            },
+            Err(err) => Err(format!("Failed to load obj file: {}", err)),
+        }
+    }
+
 }
 
 impl Hittable for Mesh {
-    fn hit(&self, r: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         self.tris.hit(r, t_min, t_max)
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.tris.bounding_box(t0, t1)
     }
-}
\ No newline at end of file
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        // every triangle along v could have been sampled, weighted by its selection probability
+        self.weighted.as_ref().map_or(0.0, |bvh| bvh.pdf_sum(o, v))
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        let (index, _) = self.areas.sample_discrete(rand::thread_rng().gen::<f64>());
+        self.tris.list[index].random(o)
+    }
+
+    fn power(&self) -> f64 {
+        self.tris.power()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        self.tris.light_bounds()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        // triangles share the material, so area is proportional to power
+        let (index, _) = self.areas.sample_discrete(rand::thread_rng().gen::<f64>());
+        self.tris.list[index].sample_surface(time).map(|mut sample| {
+            sample.pdf *= self.areas.discrete_pdf(index);
+            sample
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mat::Lambertian;
+    use crate::texture::ConstantTexture;
+    use crate::vec::Color;
+
+    fn white() -> Lambertian<ConstantTexture> {
+        Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)))
+    }
+
+    // a closed octahedron crossed twice by every direction that reaches it, and a flat axis aligned pair of
+    // triangles of different sizes
+    fn meshes() -> Vec<Mesh> {
+        let octahedron = vec![
+            Point3::new(1.0, 0.0, 0.0), Point3::new(-1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0),
+            Point3::new(0.0, -1.0, 0.0), Point3::new(0.0, 0.0, 1.5), Point3::new(0.0, 0.0, -1.0)
+        ];
+        let faces = vec![0, 2, 4, 2, 1, 4, 1, 3, 4, 3, 0, 4, 2, 0, 5, 1, 2, 5, 3, 1, 5, 0, 3, 5];
+        let flat = vec![
+            Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, -1.0, -1.0), Point3::new(1.0, 0.5, -1.0), Point3::new(-1.0, 2.0, -1.0)
+        ];
+        vec![Mesh::new(octahedron, faces, white()), Mesh::new(flat, vec![0, 1, 2, 0, 2, 3], white())]
+    }
+
+    #[test]
+    fn sampled_directions_hit_the_mesh() {
+        let o = Point3::new(0.5, 0.3, 2.5);
+        for mesh in meshes() {
+            for _ in 0..10000 {
+                let v = mesh.random(o);
+                let pdf = mesh.pdf_value(o, v);
+                assert!(pdf > 0.0);
+                // the BVH finds the same triangles as trying every one of them
+                let every: f64 = mesh.tris.list.iter().enumerate().map(|(i, tri)| mesh.areas.discrete_pdf(i) * tri.pdf_value(o, v)).sum();
+                assert!((pdf - every).abs() <= 1e-9 * every);
+            }
+        }
+    }
+
+    #[test]
+    fn mesh_pdf_is_normalized() {
+        let o = Point3::new(0.5, 0.3, 2.5);
+        const SAMPLES: usize = 400000;
+        for mesh in meshes() {
+            // uniform directions, every one of them with density 1 / 4pi
+            let total = (0..SAMPLES).map(|_| {
+                let v = Vec3::random_in_unit_sphere().normalized();
+                4.0 * f64::consts::PI * mesh.pdf_value(o, v)
+            }).sum::<f64>() / SAMPLES as f64;
+            assert!((total - 1.0).abs() < 0.03, "the mesh pdf integrates to {}", total);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use super::vec::Vec3;
 
+#[derive(Clone, Copy)]
 pub struct ONB {
     axis: [Vec3; 3]
 }
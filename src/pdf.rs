@@ -4,6 +4,7 @@ use super::hit::Hittable;
 use super::vec::{Vec3, Point3};
 use super::onb::ONB;
 use super::mat;
+use super::phase::PhaseFunction;
 
 fn random_cosine_direction() -> Vec3 {
     let mut rng = rand::thread_rng();
@@ -21,60 +22,94 @@ fn spherical_direction(sin_theta: f64, cos_theta: f64, sin_phi: f64, cos_phi: f6
     Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
 }
 
-fn GTR_1_direction(r_in: Vec3, clearcoat_gloss: f64) -> Vec3 {
+// microfacet normal of the GTR1 clearcoat distribution, importance sampled by D cos in the local frame
+fn gtr_1_normal(alpha: f64) -> Vec3 {
     let mut rng = rand::thread_rng();
-    let r1 = rng.gen_range(0.0..1.0);
-    let r2 = rng.gen_range(0.0..1.0);
-    let a = mat::mix(0.1, 0.001, clearcoat_gloss); 
-    let a2 = a * a;
-    let cos_theta = f64::sqrt(f64::max(0.001, (1.0 - a2.powf(1.0 - r1)) / (1.0 - a2)));
-    let sin_theta = f64::sqrt(f64::max(0.001, 1.0 - cos_theta * cos_theta));
-    let phi = f64::consts::PI * 2.0 * r2;
-    let wh = spherical_direction(sin_theta, cos_theta, f64::sin(phi), f64::cos(phi));
-
-    Vec3::reflect(r_in, wh)
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - a2.powf(1.0 - rng.gen::<f64>())) / (1.0 - a2)).clamp(0.0, 1.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = f64::consts::PI * 2.0 * rng.gen::<f64>();
+    spherical_direction(sin_theta, cos_theta, phi.sin(), phi.cos())
 }
 
-fn GTR_2_aniso_direction(r_in: Vec3, roughness: f64, anisotropic: f64) -> Vec3 {
+// microfacet normal of the GGX distribution as seen from wo, both in the local frame with the normal along z
+// and the alphas along its two tangents
+fn ggx_visible_normal(wo: Vec3, ax: f64, ay: f64) -> Vec3 {
     let mut rng = rand::thread_rng();
-    let r1 = rng.gen_range(0.0..1.0);
-    let r2 = rng.gen_range(0.0..1.0);
-    let aspect = (1.0 - anisotropic * 0.9).sqrt();
-    let ax = (roughness.powi(2) / aspect).max(0.001);
-    let ay = (roughness.powi(2) * aspect).max(0.001);
-    let mut phi = f64::atan(ay / ax * f64::tan(2.0 * f64::consts::PI * r2 + 0.5 * f64::consts::PI));
-    if r2 > 0.5 {
-        phi += f64::consts::PI;
+    // stretch to the configuration where the microfacets form a hemisphere
+    let vh = Vec3::new(ax * wo.x(), ay * wo.y(), wo.z()).normalized();
+    let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if lensq > 0.0 { Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+    let t2 = vh.cross(t1);
+    let r = rng.gen::<f64>().sqrt();
+    let phi = 2.0 * f64::consts::PI * rng.gen::<f64>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    Vec3::new(ax * nh.x(), ay * nh.y(), nh.z().max(0.0)).normalized()
+}
+
+// density of the visible normal h seen from the unit direction wo, in the frame of uvw
+fn ggx_visible_pdf(uvw: &ONB, wo: Vec3, h: Vec3, ax: f64, ay: f64) -> f64 {
+    let n = uvw.w();
+    let cos_o = n.dot(wo);
+    let g1 = 1.0 / (1.0 + mat::ggx_lambda_aniso(cos_o, wo.dot(uvw.u()), wo.dot(uvw.v()), ax, ay));
+    g1 * wo.dot(h).max(0.0) * mat::GTR_2_aniso(n.dot(h), h.dot(uvw.u()), h.dot(uvw.v()), ax, ay) / cos_o
+}
+
+// reflection off the isotropic GGX microfacets visible from wo, the visible normal density over the jacobian
+// of reflecting about the normal
+fn ggx_reflection_pdf(uvw: &ONB, wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    let (cos_o, cos_i) = (uvw.w().dot(wo), uvw.w().dot(wi));
+    if cos_o <= 0.0 || cos_i <= 0.0 {
+        return 0.0;
     }
-    let sin_phi = f64::sin(phi);
-    let cos_phi = f64::cos(phi);
-    let ax_2 = ax * ax;
-    let ay_2 = ay * ay;
-    let a2 = 1.0 / (cos_phi * cos_phi / ax_2 + sin_phi * sin_phi / ay_2);
-    let tan_theta_2 = a2 * r1 / (1.0 - r1);
-    let cos_theta = 1.0 / (1.0 + tan_theta_2).sqrt();
-    let sin_theta = f64::sqrt(f64::max(0.001, 1.0 - cos_theta * cos_theta));
-    let wh = spherical_direction(sin_theta, cos_theta, f64::sin(phi), f64::cos(phi));
-
-    Vec3::reflect(r_in, wh)
+    let h = (wo + wi).normalized();
+    mat::ggx_g1(cos_o, alpha) * mat::GTR_2(uvw.w().dot(h), alpha) / (4.0 * cos_o)
+}
+
+fn to_local(uvw: &ONB, w: Vec3) -> Vec3 {
+    Vec3::new(w.dot(uvw.u()), w.dot(uvw.v()), w.dot(uvw.w()))
+}
+
+// multiple importance sampling weight of a sample drawn with f_pdf, against another strategy that could draw it with g_pdf
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2 + g2 > 0.0 { f2 / (f2 + g2) } else { 0.0 }
 }
 
 pub enum PDF<'a> {
-    BRDF { uvw: ONB, r_in: Vec3, roughness: f64, anisotropic: f64, clearcoat: f64, clearcoat_gloss: f64 },
+    // the principled bsdf's diffuse, specular, transmission and clearcoat lobes picked with the probabilities in lobes,
+    // eta as for RoughDielectric and thin sheets transmit the reflection mirrored through the surface
+    Principled { uvw: ONB, wo: Vec3, alpha: (f64, f64), clearcoat_alpha: f64, eta: f64, thin: bool, lobes: [f64; 4] },
     Cosine { uvw: ONB },
-    Hittable { origin: Point3, hittable: &'a Box<dyn Hittable> },
-    Mixture { p0: &'a PDF<'a>, p1: &'a PDF<'a> }
+    // reflection off GGX microfacets visible from the unit direction wo leaving the surface
+    GGX { uvw: ONB, wo: Vec3, alpha: f64 },
+    // reflection and refraction through them, w on the side of wo and eta the index there over the other one
+    RoughDielectric { uvw: ONB, wo: Vec3, alpha: f64, eta: f64 },
+    // a coated surface, reflection off the coat's microfacets with probability coat and otherwise the base's own
+    // sampling or a cosine lobe for light spread out between the layers
+    Layered { uvw: ONB, wo: Vec3, alpha: f64, coat: f64, base: Option<Box<PDF<'a>>> },
+    // incoming is the unit direction the light was travelling in
+    Phase { function: PhaseFunction, incoming: Vec3 },
+    Hittable { origin: Point3, hittable: &'a dyn Hittable },
+    Mixture { p0: &'a PDF<'a>, p1: &'a PDF<'a> },
+    // a mixture that owns its parts, for materials that add up the lobes of two others. weight is the chance of p1
+    Blend { p0: Box<PDF<'a>>, p1: Box<PDF<'a>>, weight: f64 }
 }
 
 impl<'a> PDF<'a> {
-    pub fn brdf_pdf(w: Vec3, r_in: Vec3, roughness: f64, anisotropic: f64, clearcoat: f64, clearcoat_gloss: f64)-> PDF<'a> {
-        PDF::BRDF {
+    pub fn principled_pdf(w: Vec3, r_in: Vec3, alpha: (f64, f64), clearcoat_alpha: f64, eta: f64, thin: bool, lobes: [f64; 4]) -> PDF<'a> {
+        PDF::Principled {
             uvw: ONB::build_from_w(&w),
-            r_in,
-            roughness,
-            anisotropic,
-            clearcoat,
-            clearcoat_gloss
+            wo: r_in.normalized() * (-1.0),
+            alpha,
+            clearcoat_alpha,
+            eta,
+            thin,
+            lobes
         }
     }
 
@@ -84,7 +119,39 @@ impl<'a> PDF<'a> {
         }
     }
 
-    pub fn hittable_pdf(origin: Point3, hittable: &'a Box<dyn Hittable>) -> PDF<'a> {
+    // r_in is the direction arriving at the surface
+    pub fn ggx_pdf(w: Vec3, r_in: Vec3, alpha: f64) -> PDF<'a> {
+        PDF::GGX {
+            uvw: ONB::build_from_w(&w),
+            wo: r_in.normalized() * (-1.0),
+            alpha
+        }
+    }
+
+    pub fn rough_dielectric_pdf(w: Vec3, r_in: Vec3, alpha: f64, eta: f64) -> PDF<'a> {
+        PDF::RoughDielectric {
+            uvw: ONB::build_from_w(&w),
+            wo: r_in.normalized() * (-1.0),
+            alpha,
+            eta
+        }
+    }
+
+    pub fn layered_pdf(w: Vec3, r_in: Vec3, alpha: f64, coat: f64, base: Option<Box<PDF<'a>>>) -> PDF<'a> {
+        PDF::Layered {
+            uvw: ONB::build_from_w(&w),
+            wo: r_in.normalized() * (-1.0),
+            alpha,
+            coat,
+            base
+        }
+    }
+
+    pub fn phase_pdf(function: PhaseFunction, incoming: Vec3) -> PDF<'a> {
+        PDF::Phase { function, incoming: incoming.normalized() }
+    }
+
+    pub fn hittable_pdf(origin: Point3, hittable: &'a dyn Hittable) -> PDF<'a> {
         PDF::Hittable { origin, hittable }
     }
 
@@ -92,41 +159,45 @@ impl<'a> PDF<'a> {
         PDF::Mixture { p0, p1 }
     }
 
+    pub fn blend_pdf(p0: PDF<'a>, p1: PDF<'a>, weight: f64) -> PDF<'a> {
+        PDF::Blend { p0: Box::new(p0), p1: Box::new(p1), weight }
+    }
+
     pub fn value(&self, r_out: Vec3) -> f64 {
         match self {
-            PDF::BRDF { uvw, r_in, roughness, anisotropic, clearcoat, clearcoat_gloss } => {
-                let cosine = r_out.normalized().dot(uvw.w());
-                if cosine <= 0.0 {
-                    return 0.0;
-                }
-                // diffuse
-                let diffuse_pdf = cosine / f64::consts::PI;
-
-                let l= r_in.normalized() * (-1.0);
-                let v = r_out.normalized();
+            PDF::Principled { uvw, wo, alpha, clearcoat_alpha, eta, thin, lobes } => {
+                let wi = r_out.normalized();
                 let n = uvw.w();
-                let x = uvw.u();
-                let y = uvw.v();
-                let n_dot_v = n.dot(v);
-                let n_dot_l = n.dot(l);
-                let h = (l + v).normalized();
-                let n_dot_h = n.dot(h);
-                let l_dot_h = l.dot(h);
-
-                if n_dot_h <= 0.0 {
+                let (cos_o, cos_i) = (n.dot(*wo), n.dot(wi));
+                if cos_o <= 0.0 || cos_i == 0.0 {
                     return 0.0;
                 }
+                let [diffuse, specular, transmission, clearcoat] = *lobes;
+                let (ax, ay) = *alpha;
 
-                // specular
-                let aspect = (1.0 - anisotropic * 0.9).sqrt();
-                let ax = (roughness.powi(2) / aspect).max(0.001);
-                let ay = (roughness.powi(2) * aspect).max(0.001);
-                let specular_pdf = mat::GTR_2_aniso(n_dot_h, h.dot(x), h.dot(y), ax, ay) * f64::abs(n_dot_h) * 0.25  / n_dot_l;
+                if cos_i < 0.0 {
+                    if *thin {
+                        let (tx, ty) = mat::thin_transmission_alpha(*alpha, *eta);
+                        let h = (*wo + wi - 2.0 * cos_i * n).normalized();
+                        return transmission * ggx_visible_pdf(uvw, *wo, h, tx, ty) / (4.0 * wo.dot(h));
+                    }
+                    let h = match mat::refraction_half_vector(*wo, wi, n, *eta) {
+                        Some(h) => h,
+                        None => return 0.0
+                    };
+                    let (o_dot_h, i_dot_h) = (wo.dot(h), wi.dot(h));
+                    // the jacobian of refracting is over i.h, not the reflection's 4 o.h
+                    let jacobian = i_dot_h.abs() / (i_dot_h + eta * o_dot_h).powi(2);
+                    return transmission * ggx_visible_pdf(uvw, *wo, h, ax, ay) * jacobian;
+                }
 
-                // clearcoat
-                let clearcoat_pdf = mat::GTR_1(n_dot_h, mat::mix(0.1, 0.001, *clearcoat_gloss)) * f64::abs(n_dot_h) * 0.25  / n_dot_l;
+                let h = (*wo + wi).normalized();
+                let (n_dot_h, o_dot_h) = (n.dot(h), wo.dot(h));
+                let diffuse_pdf = cos_i / f64::consts::PI;
+                let specular_pdf = ggx_visible_pdf(uvw, *wo, h, ax, ay) / (4.0 * o_dot_h);
+                let clearcoat_pdf = mat::GTR_1(n_dot_h, *clearcoat_alpha) * n_dot_h / (4.0 * o_dot_h);
 
-                (diffuse_pdf + specular_pdf + clearcoat_pdf) / 3.0
+                diffuse * diffuse_pdf + specular * specular_pdf + clearcoat * clearcoat_pdf
             },
             PDF::Cosine { uvw } => {
                 let cosine = r_out.normalized().dot(uvw.w());
@@ -137,32 +208,126 @@ impl<'a> PDF<'a> {
                     0.0
                 }
             },
+            PDF::GGX { uvw, wo, alpha } => {
+                ggx_reflection_pdf(uvw, *wo, r_out.normalized(), *alpha)
+            },
+            PDF::RoughDielectric { uvw, wo, alpha, eta } => {
+                let wi = r_out.normalized();
+                let n = uvw.w();
+                let (cos_o, cos_i) = (n.dot(*wo), n.dot(wi));
+                if cos_o <= 0.0 || cos_i == 0.0 {
+                    return 0.0;
+                }
+                if cos_i > 0.0 {
+                    let h = (*wo + wi).normalized();
+                    mat::fresnel_dielectric(wo.dot(h), *eta) * mat::ggx_g1(cos_o, *alpha) * mat::GTR_2(n.dot(h), *alpha) / (4.0 * cos_o)
+                } else {
+                    let h = match mat::refraction_half_vector(*wo, wi, n, *eta) {
+                        Some(h) => h,
+                        None => return 0.0
+                    };
+                    let (o_dot_h, i_dot_h) = (wo.dot(h), wi.dot(h));
+                    let visible = mat::ggx_g1(cos_o, *alpha) * mat::GTR_2(n.dot(h), *alpha) * o_dot_h / cos_o;
+                    let jacobian = i_dot_h.abs() / (i_dot_h + eta * o_dot_h).powi(2);
+                    (1.0 - mat::fresnel_dielectric(o_dot_h, *eta)) * visible * jacobian
+                }
+            },
+            PDF::Layered { uvw, wo, alpha, coat, base } => {
+                let cosine = r_out.normalized().dot(uvw.w()).max(0.0) / f64::consts::PI;
+                let through = match base {
+                    Some(base) => 0.75 * base.value(r_out) + 0.25 * cosine,
+                    None => cosine
+                };
+                coat * ggx_reflection_pdf(uvw, *wo, r_out.normalized(), *alpha) + (1.0 - coat) * through
+            },
+            PDF::Phase { function, incoming } => {
+                function.value(*incoming, r_out.normalized())
+            },
             PDF::Hittable { origin, hittable } => {
                 hittable.pdf_value(*origin, r_out)
             },
             PDF::Mixture { p0, p1 } => {
                 0.5 * p0.value(r_out) + 0.5 * p1.value(r_out)
+            },
+            PDF::Blend { p0, p1, weight } => {
+                (1.0 - weight) * p0.value(r_out) + weight * p1.value(r_out)
             }
         }
     }
 
-    pub fn generate(&self) -> Vec3 {
+    // None where the sample is lost, like a microfacet sending it to the wrong side of the surface
+    pub fn generate(&self) -> Option<Vec3> {
         match self {
-            PDF::BRDF { uvw, r_in, roughness, anisotropic, clearcoat, clearcoat_gloss } => {
-                let mut rng = rand::thread_rng().gen_range(0.0..1.0);
-                if rng < 0.333 {
-                    uvw.local(&random_cosine_direction())
-                } else if rng < 0.666 {
-                    uvw.local(&GTR_1_direction(*r_in, *clearcoat_gloss))
+            PDF::Principled { uvw, wo, alpha, clearcoat_alpha, eta, thin, lobes } => {
+                let [diffuse, specular, transmission, _] = *lobes;
+                let (ax, ay) = *alpha;
+                let local = to_local(uvw, *wo);
+                let u = rand::thread_rng().gen::<f64>();
+                if u < diffuse {
+                    return Some(uvw.local(&random_cosine_direction()));
+                }
+
+                let (wi, below) = if u < diffuse + specular {
+                    let h = uvw.local(&ggx_visible_normal(local, ax, ay));
+                    (((-1.0) * *wo).reflect(h), false)
+                } else if u < diffuse + specular + transmission {
+                    if *thin {
+                        let (tx, ty) = mat::thin_transmission_alpha(*alpha, *eta);
+                        let h = uvw.local(&ggx_visible_normal(local, tx, ty));
+                        let reflected = ((-1.0) * *wo).reflect(h);
+                        (reflected - 2.0 * reflected.dot(uvw.w()) * uvw.w(), true)
+                    } else {
+                        let h = uvw.local(&ggx_visible_normal(local, ax, ay));
+                        if mat::fresnel_dielectric(wo.dot(h), *eta) >= 1.0 {
+                            // total internal reflection off this microfacet, nothing to refract
+                            return None;
+                        }
+                        (((-1.0) * *wo).refract(h, *eta), true)
+                    }
                 } else {
-                    uvw.local(&GTR_2_aniso_direction(*r_in, *roughness, *anisotropic))
+                    let h = uvw.local(&gtr_1_normal(*clearcoat_alpha));
+                    (((-1.0) * *wo).reflect(h), false)
+                };
+                // samples that end up on the wrong side of the surface are lost, value does not count them
+                if below != (wi.dot(uvw.w()) < 0.0) {
+                    return None;
                 }
+                Some(wi)
             },
             PDF::Cosine { uvw } => {
-                uvw.local(&random_cosine_direction())
+                Some(uvw.local(&random_cosine_direction()))
+            },
+            PDF::GGX { uvw, wo, alpha } => {
+                let h = uvw.local(&ggx_visible_normal(to_local(uvw, *wo), *alpha, *alpha));
+                Some(((-1.0) * *wo).reflect(h))
+            },
+            PDF::RoughDielectric { uvw, wo, alpha, eta } => {
+                let h = uvw.local(&ggx_visible_normal(to_local(uvw, *wo), *alpha, *alpha));
+                let o_dot_h = wo.dot(h);
+                let reflect = rand::thread_rng().gen::<f64>() < mat::fresnel_dielectric(o_dot_h, *eta);
+                let wi = if reflect { ((-1.0) * *wo).reflect(h) } else { ((-1.0) * *wo).refract(h, *eta) };
+                // a microfacet can send light to the wrong side of the surface, value does not count those
+                if reflect != (wi.dot(uvw.w()) > 0.0) {
+                    return None;
+                }
+                Some(wi)
+            },
+            PDF::Layered { uvw, wo, alpha, coat, base } => {
+                let u = rand::thread_rng().gen::<f64>();
+                if u < *coat {
+                    let h = uvw.local(&ggx_visible_normal(to_local(uvw, *wo), *alpha, *alpha));
+                    return Some(((-1.0) * *wo).reflect(h));
+                }
+                match base {
+                    Some(base) if u < coat + 0.75 * (1.0 - coat) => base.generate(),
+                    _ => Some(uvw.local(&random_cosine_direction()))
+                }
+            },
+            PDF::Phase { function, incoming } => {
+                Some(function.sample(*incoming))
             },
             PDF::Hittable { origin, hittable } => {
-                hittable.random(*origin)
+                Some(hittable.random(*origin))
             },
             PDF::Mixture { p0, p1 } => {
                 let mut rng = rand::thread_rng();
@@ -171,6 +336,13 @@ impl<'a> PDF<'a> {
                 } else {
                     p1.generate()
                 }
+            },
+            PDF::Blend { p0, p1, weight } => {
+                if rand::thread_rng().gen::<f64>() < *weight {
+                    p1.generate()
+                } else {
+                    p0.generate()
+                }
             }
         }
     }
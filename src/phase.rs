@@ -0,0 +1,135 @@
+use std::f64;
+use rand::Rng;
+use super::vec::Vec3;
+use super::onb::ONB;
+
+// how a medium spreads light around the direction it was travelling in
+#[derive(Clone, Copy)]
+pub enum PhaseFunction {
+    Isotropic,
+    // g in (-1, 1), positive scatters forward like haze and clouds, negative backward
+    HenyeyGreenstein { g: f64 },
+    // a forward and a backward lobe, weight goes to the first
+    DoubleHenyeyGreenstein { g_forward: f64, g_backward: f64, weight: f64 },
+    // particles much smaller than the wavelength, like the molecules of a clear sky
+    Rayleigh
+}
+
+fn henyey_greenstein(g: f64, cos_theta: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * f64::consts::PI * denominator * denominator.max(1e-12).sqrt())
+}
+
+// cosine to the travel direction, drawn in proportion to the henyey greenstein lobe
+fn sample_henyey_greenstein(g: f64, u: f64) -> f64 {
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u
+    }
+    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+
+impl PhaseFunction {
+    // density of scattering light travelling along incoming into outgoing, both unit vectors, doubles as its sampling pdf
+    pub fn value(&self, incoming: Vec3, outgoing: Vec3) -> f64 {
+        let cos_theta = incoming.dot(outgoing);
+        match *self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * f64::consts::PI),
+            PhaseFunction::HenyeyGreenstein { g } => henyey_greenstein(g, cos_theta),
+            PhaseFunction::DoubleHenyeyGreenstein { g_forward, g_backward, weight } =>
+                weight * henyey_greenstein(g_forward, cos_theta) + (1.0 - weight) * henyey_greenstein(g_backward, cos_theta),
+            PhaseFunction::Rayleigh => 3.0 / (16.0 * f64::consts::PI) * (1.0 + cos_theta * cos_theta)
+        }
+    }
+
+    // scattered direction for light travelling along incoming, distributed exactly like value
+    pub fn sample(&self, incoming: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let u = rng.gen::<f64>();
+        let cos_theta = match *self {
+            PhaseFunction::Isotropic => 1.0 - 2.0 * u,
+            PhaseFunction::HenyeyGreenstein { g } => sample_henyey_greenstein(g, u),
+            PhaseFunction::DoubleHenyeyGreenstein { g_forward, g_backward, weight } =>
+                if rng.gen::<f64>() < weight { sample_henyey_greenstein(g_forward, u) } else { sample_henyey_greenstein(g_backward, u) },
+            PhaseFunction::Rayleigh => {
+                // inverts the cdf, the cubic cos^3 + 3 cos = q has a single real root
+                let q = 4.0 * (2.0 * u - 1.0);
+                let a = (0.5 * q + (0.25 * q * q + 1.0).sqrt()).cbrt();
+                (a - 1.0 / a).clamp(-1.0, 1.0)
+            }
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f64::consts::PI * rng.gen::<f64>();
+        ONB::build_from_w(&incoming).local(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phase_functions() -> Vec<PhaseFunction> {
+        vec![
+            PhaseFunction::Isotropic,
+            PhaseFunction::HenyeyGreenstein { g: 0.9 },
+            PhaseFunction::HenyeyGreenstein { g: -0.4 },
+            PhaseFunction::HenyeyGreenstein { g: 0.0005 },
+            PhaseFunction::DoubleHenyeyGreenstein { g_forward: 0.8, g_backward: -0.5, weight: 0.7 },
+            PhaseFunction::Rayleigh
+        ]
+    }
+
+    // the phase functions only depend on the cosine, so the sphere integrates as 2pi times a line over it
+    fn integrate_cosine(phase: &PhaseFunction, from: f64, to: f64) -> f64 {
+        const STEPS: usize = 2000;
+        let incoming = Vec3::new(0.0, 0.0, 1.0);
+        let width = (to - from) / STEPS as f64;
+        (0..STEPS).map(|i| {
+            let cos_theta = from + (i as f64 + 0.5) * width;
+            let outgoing = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+            phase.value(incoming, outgoing)
+        }).sum::<f64>() * width * 2.0 * f64::consts::PI
+    }
+
+    #[test]
+    fn phase_functions_are_normalized() {
+        for phase in phase_functions() {
+            // the strongly forward lobe is narrow, integrate it in finer pieces
+            let total: f64 = (0..100).map(|i| integrate_cosine(&phase, -1.0 + 0.02 * i as f64, -0.98 + 0.02 * i as f64)).sum();
+            assert!((total - 1.0).abs() < 1e-4, "integrates to {}", total);
+        }
+    }
+
+    #[test]
+    fn phase_sampling_follows_the_phase_function() {
+        const SAMPLES: usize = 200000;
+        const BINS: usize = 20;
+        let incoming = Vec3::new(1.0, 2.0, 3.0).normalized();
+        for phase in phase_functions() {
+            let mut histogram = [0usize; BINS];
+            for _ in 0..SAMPLES {
+                let outgoing = phase.sample(incoming);
+                assert!((outgoing.length() - 1.0).abs() < 1e-9);
+                let bin = ((0.5 * (outgoing.dot(incoming) + 1.0) * BINS as f64) as usize).min(BINS - 1);
+                histogram[bin] += 1;
+            }
+            for (bin, &count) in histogram.iter().enumerate() {
+                let from = -1.0 + 2.0 * bin as f64 / BINS as f64;
+                let expected = integrate_cosine(&phase, from, from + 2.0 / BINS as f64);
+                let found = count as f64 / SAMPLES as f64;
+                assert!((found - expected).abs() < 0.006, "bin {} holds {} of the samples instead of {}", bin, found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn mean_cosines_match_the_asymmetry() {
+        const SAMPLES: usize = 200000;
+        let incoming = Vec3::new(0.0, 1.0, 0.0);
+        let mean = |phase: PhaseFunction| (0..SAMPLES).map(|_| phase.sample(incoming).dot(incoming)).sum::<f64>() / SAMPLES as f64;
+        assert!((mean(PhaseFunction::HenyeyGreenstein { g: 0.6 }) - 0.6).abs() < 0.01);
+        assert!((mean(PhaseFunction::HenyeyGreenstein { g: -0.3 }) + 0.3).abs() < 0.01);
+        assert!((mean(PhaseFunction::DoubleHenyeyGreenstein { g_forward: 0.8, g_backward: -0.5, weight: 0.7 }) - 0.41).abs() < 0.01);
+        assert!(mean(PhaseFunction::Rayleigh).abs() < 0.01);
+    }
+}
@@ -0,0 +1,327 @@
+use std::f64;
+use std::io::{stderr, Write};
+use rand::Rng;
+use rayon::prelude::*;
+use super::vec::{Vec3, Point3, Color};
+use super::ray::Ray;
+use super::hit::HitRecord;
+use super::mat::ScatterRecord;
+use super::pdf::{PDF, power_heuristic};
+use super::camera::Camera;
+use super::light::LightEmitter;
+use super::interior::InteriorStack;
+use super::medium::Medium;
+use super::film::Film;
+use super::SceneRefs;
+
+// how fast the radius shrinks between passes, between 0 and 1
+const ALPHA: f64 = 2.0 / 3.0;
+
+// light that landed on a diffuse surface after at least one bounce
+#[derive(Clone, Copy)]
+struct Photon {
+    position: Point3,
+    // unit direction the photon travelled in
+    direction: Vec3,
+    power: Color
+}
+
+// balanced kd-tree, the median of every subslice sits in its middle
+struct PhotonMap {
+    photons: Vec<Photon>,
+    // split axis of the node stored at the same index
+    axes: Vec<usize>,
+    // of the density estimate in the pass the map was traced for
+    radius: f64
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>, radius: f64) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        PhotonMap::build(&mut photons, &mut axes);
+        PhotonMap {
+            photons,
+            axes,
+            radius
+        }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [usize]) {
+        if photons.is_empty() {
+            return
+        }
+        // split along the widest extent
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for photon in photons.iter() {
+            for a in 0..3 {
+                min[a] = min[a].min(photon.position[a]);
+                max[a] = max[a].max(photon.position[a]);
+            }
+        }
+        let extent = max - min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() { 0 } else if extent.y() > extent.z() { 1 } else { 2 };
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| a.position[axis].partial_cmp(&b.position[axis]).unwrap());
+        axes[mid] = axis;
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        PhotonMap::build(left, left_axes);
+        PhotonMap::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    // visits every photon within the radius of position
+    fn query(&self, position: Point3, visit: &mut impl FnMut(&Photon)) {
+        self.query_range(0, self.photons.len(), position, self.radius * self.radius, visit);
+    }
+
+    fn query_range(&self, lo: usize, hi: usize, position: Point3, radius_squared: f64, visit: &mut impl FnMut(&Photon)) {
+        if lo >= hi {
+            return
+        }
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let offset = position - photon.position;
+        if offset.dot(offset) <= radius_squared {
+            visit(photon);
+        }
+        let delta = offset[self.axes[mid]];
+        if delta <= 0.0 || delta * delta <= radius_squared {
+            self.query_range(lo, mid, position, radius_squared, visit);
+        }
+        if delta >= 0.0 || delta * delta <= radius_squared {
+            self.query_range(mid + 1, hi, position, radius_squared, visit);
+        }
+    }
+}
+
+// progressive photon mapping: every pass traces a new photon map and estimates with a smaller radius, so the average converges
+pub struct PhotonMapper<'a> {
+    camera: &'a Camera,
+    scene: SceneRefs<'a>,
+    // photons traced per pass
+    photons: usize,
+    // radius of the density estimate in the first pass
+    radius: f64,
+    // longest photon and camera paths in bounces
+    max_depth: usize,
+    emitter: LightEmitter<'a>
+}
+
+impl<'a> PhotonMapper<'a> {
+    pub fn new(camera: &'a Camera, scene: SceneRefs<'a>, photons: usize, radius: f64, max_depth: usize) -> PhotonMapper<'a> {
+        PhotonMapper {
+            camera,
+            scene,
+            photons,
+            radius,
+            max_depth,
+            emitter: LightEmitter::new(scene.lights, scene.delta_lights, scene.world)
+        }
+    }
+
+    // one pass per sample
+    pub fn render(&self, width: usize, height: usize, passes: u64) -> Film {
+        let mut film = Film::new(width, height);
+        let mut radius = self.radius;
+        for pass in 1..=passes {
+            let map = self.trace_photons(radius);
+            film = film.merge(self.estimate(width, height, &map));
+            radius *= ((pass as f64 + ALPHA) / (pass as f64 + 1.0)).sqrt();
+            eprint!("\rPasses remaining: {:3}", passes - pass);
+            stderr().flush().unwrap();
+        }
+        film
+    }
+
+    fn trace_photons(&self, radius: f64) -> PhotonMap {
+        let photons = (0..self.photons).into_par_iter().fold(Vec::new, |mut photons, _| {
+            if let Some(emitted) = self.emitter.sample(self.camera.time()) {
+                self.trace(emitted.ray, emitted.power / self.photons as f64, &mut photons);
+            }
+            photons
+        })
+        .reduce(Vec::new, |mut a, b| {
+            a.extend(b);
+            a
+        });
+        PhotonMap::new(photons, radius)
+    }
+
+    // follows a photon with the materials' own sampling, storing it at diffuse surfaces once it has bounced
+    fn trace(&self, mut ray: Ray, mut power: Color, photons: &mut Vec<Photon>) {
+        let mut rng = rand::thread_rng();
+        let mut stack = InteriorStack::default();
+        // only scattering counts towards the depth, crossing into or out of a medium is not a bounce
+        let mut depth = 0;
+        while depth < self.max_depth {
+            let rec = match stack.hit(self.scene.world, &ray) {
+                Some(rec) => rec,
+                None => return
+            };
+            power = power * rec.weight;
+            let srec = match stack.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => return
+            };
+
+            let next = match srec {
+                ScatterRecord::PassThrough { ray: through } => {
+                    ray = through;
+                    continue
+                }
+                ScatterRecord::Specular { specular_ray, attenuation } => Some((specular_ray, attenuation)),
+                ScatterRecord::Scatter { pdf, attenuation } => {
+                    if depth > 0 {
+                        photons.push(Photon { position: rec.position, direction: ray.direction().normalized(), power });
+                    }
+                    let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                    super::sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, _)| (scattered, weight))
+                }
+                ScatterRecord::Microfacet { pdf, eta } => {
+                    // light refracted by rough dielectrics is kept where it lands, not on the interface
+                    if depth > 0 && rec.material.interior().is_none() {
+                        photons.push(Photon { position: rec.position, direction: ray.direction().normalized(), power });
+                    }
+                    let f_cos = |scattered: &Ray| rec.material.bsdf(&ray, scattered, &rec, eta) * rec.normal.dot(scattered.direction()).abs();
+                    super::sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, _)| (scattered, weight))
+                }
+                // media scatter photons on toward surfaces but do not keep them
+                ScatterRecord::Volume { pdf, attenuation } => {
+                    let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                    super::sample_bsdf(&ray, &rec, &pdf, &f_cos).map(|(scattered, weight, _)| (scattered, weight))
+                }
+            };
+
+            let (next_ray, weight) = match next {
+                Some(next) => next,
+                None => return
+            };
+            stack.cross(&rec, next_ray.direction());
+            // photons keep their power, the ones absorbed are removed instead
+            let survival = weight.x().max(weight.y()).max(weight.z()).min(1.0);
+            if rng.gen::<f64>() >= survival {
+                return
+            }
+            power = power * weight / survival;
+            ray = next_ray;
+            depth += 1;
+        }
+    }
+
+    fn estimate(&self, width: usize, height: usize, map: &PhotonMap) -> Film {
+        (0..height).into_par_iter().fold(|| Film::new(width, height), |mut film, j| {
+            let mut rng = rand::thread_rng();
+            for i in 0..width {
+                let s = (i as f64 + rng.gen::<f64>()) / width as f64;
+                let t = (j as f64 + rng.gen::<f64>()) / height as f64;
+                let color = self.radiance(self.camera.get_ray(s, t), map);
+                film.add(i, j, color);
+            }
+            film
+        })
+        .reduce(|| Film::new(width, height), Film::merge)
+    }
+
+    // camera paths pass through specular bounces and media and end at the first diffuse surface
+    fn radiance(&self, mut ray: Ray, map: &PhotonMap) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut origin = ray.origin();
+        let mut stack = InteriorStack::default();
+        let mut depth = 0;
+
+        while depth < self.max_depth {
+            let rec = match stack.hit(self.scene.world, &ray) {
+                Some(rec) => rec,
+                None => {
+                    let weight = bsdf_pdf.map_or(1.0, |bsdf_pdf| power_heuristic(bsdf_pdf, self.scene.lights.pdf_value(origin, ray.direction())));
+                    return color + throughput * self.scene.background.color(&ray) * weight
+                }
+            };
+            throughput = throughput * rec.weight;
+            let emitted = rec.material.emitted(&ray, &rec);
+            if !emitted.near_zero() {
+                color += throughput * emitted * super::emission_weight(bsdf_pdf, origin, &ray, &rec, self.scene.lights);
+            }
+
+            let srec = match stack.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => break
+            };
+            match srec {
+                ScatterRecord::PassThrough { ray: through } => {
+                    ray = through;
+                    continue
+                }
+                ScatterRecord::Specular { specular_ray, attenuation } => {
+                    stack.cross(&rec, specular_ray.direction());
+                    throughput = throughput * attenuation;
+                    ray = specular_ray;
+                    bsdf_pdf = None;
+                    origin = ray.origin();
+                }
+                ScatterRecord::Scatter { pdf, attenuation } => {
+                    let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                    return color + throughput * self.gather(&ray, &rec, &pdf, &f_cos, stack.medium(), map)
+                }
+                ScatterRecord::Microfacet { pdf, eta } => {
+                    let f_cos = |scattered: &Ray| rec.material.bsdf(&ray, scattered, &rec, eta) * rec.normal.dot(scattered.direction()).abs();
+                    if rec.material.interior().is_none() {
+                        return color + throughput * self.gather(&ray, &rec, &pdf, &f_cos, stack.medium(), map)
+                    }
+                    // rough dielectrics hold no photons, the path goes through them like through a medium
+                    color += throughput * super::direct_light(&ray, &rec, &pdf, &self.scene, stack.medium(), &f_cos);
+                    match super::sample_bsdf(&ray, &rec, &pdf, &f_cos) {
+                        Some((scattered, weight, pdf)) => {
+                            stack.cross(&rec, scattered.direction());
+                            throughput = throughput * weight;
+                            ray = scattered;
+                            bsdf_pdf = Some(pdf);
+                            origin = ray.origin();
+                        }
+                        None => break
+                    }
+                }
+                ScatterRecord::Volume { pdf, attenuation } => {
+                    let f_cos = |scattered: &Ray| attenuation * rec.material.scattering_pdf(&ray, &rec, scattered);
+                    color += throughput * super::direct_light(&ray, &rec, &pdf, &self.scene, stack.medium(), &f_cos);
+                    match super::sample_bsdf(&ray, &rec, &pdf, &f_cos) {
+                        Some((scattered, weight, pdf)) => {
+                            stack.cross(&rec, scattered.direction());
+                            throughput = throughput * weight;
+                            ray = scattered;
+                            bsdf_pdf = Some(pdf);
+                            origin = ray.origin();
+                        }
+                        None => break
+                    }
+                }
+            }
+            depth += 1;
+        }
+
+        color
+    }
+
+    // direct light from light and bsdf sampling, everything that bounced before from the photons nearby
+    fn gather(&self, ray: &Ray, rec: &HitRecord, pdf: &PDF, f_cos: &impl Fn(&Ray) -> Color, medium: Option<&dyn Medium>, map: &PhotonMap) -> Color {
+        let mut color = super::direct_light(ray, rec, pdf, &self.scene, medium, f_cos);
+        if let Some((scattered, weight, bsdf_pdf)) = super::sample_bsdf(ray, rec, pdf, f_cos) {
+            color += weight * super::incident_radiance(&scattered, bsdf_pdf, &self.scene, medium);
+        }
+
+        let mut flux = Color::new(0.0, 0.0, 0.0);
+        map.query(rec.position, &mut |photon| {
+            let to_light = (-1.0) * photon.direction;
+            // photons that arrived at the other side of a thin surface do not count
+            let cosine = to_light.dot(rec.normal);
+            if cosine > 0.0 {
+                flux += f_cos(&Ray::new(rec.position, to_light, ray.time())) * photon.power / cosine;
+            }
+        });
+        color + flux / (f64::consts::PI * map.radius * map.radius)
+    }
+}
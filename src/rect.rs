@@ -1,8 +1,9 @@
 use rand::Rng;
 use super::mat::Material;
-use super::hit::{Hittable, HitRecord};
+use super::hit::{Hittable, HitRecord, SurfaceSample};
 use super::aabb::AABB;
-use super::vec::{Vec3, Point3};
+use super::light_bvh::LightBounds;
+use super::vec::{Vec3, Point3, Color};
 use super::ray::Ray;
 
 #[derive(Clone)]
@@ -70,6 +71,7 @@ impl<M: Material> Hittable for AARect<M> {
                     u,
                     v,
                     front_face: false,
+                    weight: Color::new(1.0, 1.0, 1.0),
                     material: &self.material
                 };
 
@@ -81,9 +83,16 @@ impl<M: Material> Hittable for AARect<M> {
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        // the bounding box must have non-zero width in each dimension, so pad the Z dimension a small amount.
-        let min = Vec3::new(self.a0, self.b0, self.k - 0.0001);
-        let max = Vec3::new(self.a1, self.b1, self.k + 0.0001);
+        // the bounding box must have non-zero width in each dimension, so pad the plane axis a small amount.
+        let (k_axis, a_axis, b_axis) = get_axis_index(&self.plane);
+        let mut min = Vec3::new(0.0, 0.0, 0.0);
+        let mut max = Vec3::new(0.0, 0.0, 0.0);
+        min[a_axis] = self.a0;
+        max[a_axis] = self.a1;
+        min[b_axis] = self.b0;
+        max[b_axis] = self.b1;
+        min[k_axis] = self.k - 0.0001;
+        max[k_axis] = self.k + 0.0001;
 
         Some(AABB::new(min, max))
     }
@@ -109,4 +118,38 @@ impl<M: Material> Hittable for AARect<M> {
         random_point[k_axis] = self.k;
         random_point - o
     }
+
+    fn power(&self) -> f64 {
+        let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+        let sides = if self.material.emits_both_sides() { 2.0 } else { 1.0 };
+        sides * std::f64::consts::PI * area * self.material.emitted_luminance()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        // front faces look down the positive plane axis
+        let (k_axis, _, _) = get_axis_index(&self.plane);
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        normal[k_axis] = 1.0;
+        self.bounding_box(0.0, 1.0).map(|bbox| if self.material.emits_both_sides() {
+            LightBounds::omnidirectional(bbox, self.power())
+        } else {
+            LightBounds::one_sided(bbox, self.power(), normal)
+        })
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<SurfaceSample> {
+        let mut rng = rand::thread_rng();
+        let (k_axis, a_axis, b_axis) = get_axis_index(&self.plane);
+        let mut position = Vec3::new(0.0, 0.0, 0.0);
+        position[a_axis] = rng.gen_range(self.a0..self.a1);
+        position[b_axis] = rng.gen_range(self.b0..self.b1);
+        position[k_axis] = self.k;
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        normal[k_axis] = 1.0;
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf: 1.0 / ((self.a1 - self.a0) * (self.b1 - self.b0))
+        })
+    }
 }
\ No newline at end of file
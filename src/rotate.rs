@@ -1,8 +1,9 @@
 use std::f64;
-use super::vec::Vec3;
+use super::vec::{Vec3, Color};
 use super::ray::Ray;
-use super::hit::{Hittable, HitRecord};
+use super::hit::{Hittable, HitRecord, SurfaceSample};
 use super::aabb::AABB;
+use super::light_bvh::LightBounds;
 
 #[derive(Clone)]
 pub enum Axis {
@@ -71,6 +72,23 @@ impl<H: Hittable> Rotate<H> {
             aabb
         }
     }
+
+    // world space into the frame of the wrapped hittable, the way hit turns rays
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        let (_, a_axis, b_axis) = get_axis_index(&self.axis);
+        let mut rotated = v;
+        rotated[a_axis] = self.cos_theta * v[a_axis] - self.sin_theta * v[b_axis];
+        rotated[b_axis] = self.sin_theta * v[a_axis] + self.cos_theta * v[b_axis];
+        rotated
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        let (_, a_axis, b_axis) = get_axis_index(&self.axis);
+        let mut rotated = v;
+        rotated[a_axis] = self.cos_theta * v[a_axis] + self.sin_theta * v[b_axis];
+        rotated[b_axis] = -self.sin_theta * v[a_axis] + self.cos_theta * v[b_axis];
+        rotated
+    }
 }
 
 impl<H: Hittable> Hittable for Rotate<H> {
@@ -98,14 +116,83 @@ impl<H: Hittable> Hittable for Rotate<H> {
                 normal[a_axis] = &self.cos_theta * hit.normal[a_axis] + &self.sin_theta * hit.normal[b_axis];
                 normal[b_axis] = -&self.sin_theta * hit.normal[a_axis] + &self.cos_theta * hit.normal[b_axis];
 
+                // the inner hit already faced the normal against the ray and decided front_face, a flipped
+                // normal included. rotating keeps both, set_face_normal would call every hit a front face
                 hit.position = position;
-                hit.set_face_normal(&rotated_ray, normal);
+                hit.normal = normal;
                 hit
             }
         )
     }
 
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        let rotated_ray = Ray::new(self.to_object(r.origin()), self.to_object(r.direction()), r.time());
+        self.hittable.transmittance(&rotated_ray, t_min, t_max)
+    }
+
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.aabb.clone()
     }
-}
\ No newline at end of file
+
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.hittable.pdf_value(self.to_object(o), self.to_object(v))
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.to_world(self.hittable.random(self.to_object(o)))
+    }
+
+    fn power(&self) -> f64 {
+        self.hittable.power()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        let (_, a_axis, b_axis) = get_axis_index(&self.axis);
+        match (self.hittable.light_bounds(), self.aabb) {
+            (Some(mut bounds), Some(aabb)) => {
+                let axis = bounds.axis;
+                bounds.axis[a_axis] = self.cos_theta * axis[a_axis] + self.sin_theta * axis[b_axis];
+                bounds.axis[b_axis] = -self.sin_theta * axis[a_axis] + self.cos_theta * axis[b_axis];
+                bounds.bbox = aabb;
+                Some(bounds)
+            },
+            _ => None
+        }
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        let (_, a_axis, b_axis) = get_axis_index(&self.axis);
+        self.hittable.sample_surface(time).map(|mut sample| {
+            let (position, normal) = (sample.position, sample.normal);
+            sample.position[a_axis] = self.cos_theta * position[a_axis] + self.sin_theta * position[b_axis];
+            sample.position[b_axis] = -self.sin_theta * position[a_axis] + self.cos_theta * position[b_axis];
+            sample.normal[a_axis] = self.cos_theta * normal[a_axis] + self.sin_theta * normal[b_axis];
+            sample.normal[b_axis] = -self.sin_theta * normal[a_axis] + self.cos_theta * normal[b_axis];
+            sample
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Point3;
+    use crate::hit::FlipNormal;
+    use crate::rect::{Plane, AARect};
+    use crate::mat::Lambertian;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn rotated_hits_keep_the_inner_front_face() {
+        let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+        let rect = AARect::new(Plane::XZ, -1.0, 1.0, -1.0, 1.0, 0.0, white);
+        let floor = Rotate::new(Axis::Y, rect.clone(), 30.0);
+        let ceiling = Rotate::new(Axis::Y, FlipNormal::new(rect), 30.0);
+        let down = Ray::new(Point3::new(0.1, 1.0, 0.2), Vec3::new(0.0, -1.0, 0.0), 0.0);
+
+        let hit = floor.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.front_face && hit.normal.y() > 0.0);
+        // seen from above the flipped rectangle is seen from behind, with the normal still toward the ray
+        let hit = ceiling.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!(!hit.front_face && hit.normal.y() > 0.0);
+    }
+}
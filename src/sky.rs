@@ -0,0 +1,162 @@
+use std::f64;
+use super::vec::{Vec3, Point3, Color};
+use super::ray::Ray;
+use super::hit::{Hittable, HitRecord};
+use super::aabb::AABB;
+use super::light_bvh::LightBounds;
+use super::environment::{Background, EnvironmentMap};
+use super::light::DirectionalLight;
+use super::spectrum::xyz_to_rgb;
+
+// coefficients of the Perez luminance distribution
+#[derive(Clone, Copy)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64
+}
+
+impl Perez {
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta).exp()) * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+// zenith chromaticity, [T^2, T, 1] * m * [theta^3, theta^2, theta, 1]
+fn zenith_chromaticity(m: [[f64; 4]; 3], turbidity: f64, theta_s: f64) -> f64 {
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    let s = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+    (0..3).map(|i| t[i] * (0..4).map(|j| m[i][j] * s[j]).sum::<f64>()).sum()
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    xyz_to_rgb(x / y * luminance, luminance, (1.0 - x - y) / y * luminance)
+}
+
+// Preetham et al. "A Practical Analytic Model for Daylight", radiance in kcd/m^2
+#[derive(Clone, Copy)]
+struct Preetham {
+    sun_direction: Vec3,
+    // x, y chromaticity and luminance
+    perez: [Perez; 3],
+    zenith: [f64; 3]
+}
+
+impl Preetham {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let d = direction.normalized();
+        // the horizon color continues below the horizon
+        let cos_theta = d.y().max(0.01);
+        let gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun_direction.y().clamp(0.0, 1.0).acos();
+
+        let [x, y, luminance] = [0, 1, 2].map(|i| self.zenith[i] * self.perez[i].f(cos_theta, gamma) / self.perez[i].f(1.0, theta_s));
+        xyy_to_rgb(x, y, luminance)
+    }
+}
+
+#[derive(Clone)]
+pub struct PhysicalSky {
+    model: Preetham,
+    turbidity: f64,
+    intensity: f64,
+    // tabulated copy of the sky used for importance sampling
+    table: EnvironmentMap
+}
+
+impl PhysicalSky {
+    // sun elevation above the horizon and azimuth from +x toward +z in degrees, turbidity from about 2 (clear) to 10 (hazy)
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> PhysicalSky {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_direction = Vec3::new(elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin());
+        let theta_s = f64::consts::FRAC_PI_2 - elevation.max(0.0);
+        let t = turbidity;
+
+        let perez = [
+            Perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125, d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 },
+            Perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102, d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 },
+            Perez { a: 0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251, d: 0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 }
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (f64::consts::PI - 2.0 * theta_s);
+        let zenith = [
+            zenith_chromaticity([
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886]], t, theta_s),
+            zenith_chromaticity([
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688]], t, theta_s),
+            ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0)
+        ];
+
+        let model = Preetham {
+            sun_direction,
+            perez,
+            zenith
+        };
+
+        let (width, height) = (256, 128);
+        let pixels = (0..width * height).map(|i| {
+            let theta = f64::consts::PI * ((i / width) as f64 + 0.5) / height as f64;
+            let phi = 2.0 * f64::consts::PI * ((i % width) as f64 + 0.5) / width as f64;
+            model.radiance(Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()))
+        }).collect();
+
+        PhysicalSky {
+            model,
+            turbidity,
+            intensity,
+            table: EnvironmentMap::from_pixels(width, height, pixels, intensity, 0.0)
+        }
+    }
+
+    // sun disk matching the sky, attenuated by Rayleigh and aerosol scattering along its path through the atmosphere
+    pub fn sun(&self) -> DirectionalLight {
+        let theta_s = self.model.sun_direction.y().clamp(0.0, 1.0).acos();
+        // relative optical air mass (Kasten)
+        let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-(rayleigh + aerosol) * mass).exp()
+        };
+        // wavelengths in micrometers of the red, green and blue primaries
+        let color = Color::new(transmittance(0.65), transmittance(0.57), transmittance(0.475));
+        // about 128 klux of sunlight outside the atmosphere, in the same kilo units as the sky
+        DirectionalLight::new((-1.0) * self.model.sun_direction, color, 128.0 * self.intensity, 0.53)
+    }
+}
+
+impl Background for PhysicalSky {
+    fn color(&self, ray: &Ray) -> Color {
+        self.intensity * self.model.radiance(ray.direction())
+    }
+}
+
+// like an environment map the sky is only reached by escaping rays, sampling follows the tabulated sky
+impl Hittable for PhysicalSky {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord> {
+        None
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        None
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        self.table.pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.table.random(o)
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
@@ -0,0 +1,58 @@
+use super::vec::Color;
+
+// CIE XYZ to linear sRGB, negative components (out of gamut) are clipped
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+    Color::new(r.max(0.0), g.max(0.0), b.max(0.0))
+}
+
+// piecewise gaussian of the multi-lobe fit
+fn g(lambda: f64, mu: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+    let sigma = if lambda < mu { sigma_low } else { sigma_high };
+    (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+}
+
+// CIE 1931 color matching functions, analytic fit by Wyman, Sloan and Shirley, lambda in nm
+fn color_matching(lambda: f64) -> (f64, f64, f64) {
+    let x = 1.056 * g(lambda, 599.8, 37.9, 31.0) + 0.362 * g(lambda, 442.0, 16.0, 26.7) - 0.065 * g(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * g(lambda, 568.8, 46.9, 40.5) + 0.286 * g(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * g(lambda, 437.0, 11.8, 36.0) + 0.681 * g(lambda, 459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// Planck's law, spectral radiance of a black body at lambda nm and temperature kelvin
+fn planck(lambda: f64, temperature: f64) -> f64 {
+    const C: f64 = 299792458.0;
+    const H: f64 = 6.62606957e-34;
+    const KB: f64 = 1.3806488e-23;
+    let l = lambda * 1e-9;
+    2.0 * H * C * C / (l.powi(5) * ((H * C / (l * KB * temperature)).exp() - 1.0))
+}
+
+// XYZ of a black body at the given temperature in kelvin, summed over the visible range
+fn blackbody_xyz(temperature: f64) -> (f64, f64, f64) {
+    (0..=80).map(|i| 380.0 + 5.0 * i as f64).fold((0.0, 0.0, 0.0), |(x, y, z), lambda| {
+        let radiance = planck(lambda, temperature);
+        let (cx, cy, cz) = color_matching(lambda);
+        (x + cx * radiance, y + cy * radiance, z + cz * radiance)
+    })
+}
+
+// color of a black body at the given temperature in kelvin, normalized to unit luminance
+pub fn blackbody(temperature: f64) -> Color {
+    if temperature <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0)
+    }
+    let (x, y, z) = blackbody_xyz(temperature);
+    xyz_to_rgb(x / y, 1.0, z / y)
+}
+
+// luminance of a black body in arbitrary units, only meaningful relative to other temperatures
+pub fn blackbody_luminance(temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0
+    }
+    blackbody_xyz(temperature).1
+}
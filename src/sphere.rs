@@ -1,8 +1,8 @@
 use std::f64;
 use rand::Rng;
-use super::vec::{Vec3, Point3};
+use super::vec::{Vec3, Point3, Color};
 use super::ray::Ray;
-use super::hit::{Hittable, HitRecord};
+use super::hit::{Hittable, HitRecord, SurfaceSample};
 use super::mat::Material;
 use super::aabb;
 use super::aabb::AABB;
@@ -81,6 +81,7 @@ impl<M: Material> Hittable for Sphere<M> {
             u: 0.0,
             v: 0.0,
             front_face: false,
+            weight: Color::new(1.0, 1.0, 1.0),
             material: &self.material
         };
 
@@ -117,6 +118,20 @@ impl<M: Material> Hittable for Sphere<M> {
         let uvw = ONB::build_from_w(&direction);
         uvw.local(&random_to_sphere(self.radius, distance_squared))
     }
+
+    fn power(&self) -> f64 {
+        let area = 4.0 * f64::consts::PI * self.radius.powi(2);
+        f64::consts::PI * area * self.material.emitted_luminance()
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<SurfaceSample> {
+        let normal = Vec3::random_in_unit_sphere().normalized();
+        Some(SurfaceSample {
+            position: self.center + self.radius * normal,
+            normal,
+            pdf: 1.0 / (4.0 * f64::consts::PI * self.radius.powi(2))
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -175,6 +190,7 @@ impl<M: Material> Hittable for MovingSphere<M> {
             u: 0.0,
             v: 0.0,
             front_face: false,
+            weight: Color::new(1.0, 1.0, 1.0),
             material: &self.material
         };
 
@@ -0,0 +1,431 @@
+use std::f64;
+use std::collections::HashMap;
+use super::vec::{Vec3, Point3};
+
+#[derive(Clone, Copy)]
+pub enum Subdivision {
+    // triangle meshes, polygons are fan triangulated first
+    Loop(u32),
+    // any polygon mesh, quads after the first level
+    CatmullClark(u32)
+}
+
+struct Edge {
+    vertices: (usize, usize),
+    faces: Vec<usize>
+}
+
+// shared edge/vertex connectivity of a polygon mesh
+struct Topology {
+    edges: Vec<Edge>,
+    edge_index: HashMap<(usize, usize), usize>,
+    // every vertex connected by an edge
+    neighbors: Vec<Vec<usize>>,
+    // neighbors connected by a boundary or non-manifold edge
+    creases: Vec<Vec<usize>>
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+impl Topology {
+    fn build(vertex_count: usize, faces: &[Vec<usize>]) -> Topology {
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut edge_index = HashMap::new();
+
+        for (f, face) in faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let key = edge_key(face[i], face[(i + 1) % face.len()]);
+                let id = *edge_index.entry(key).or_insert_with(|| {
+                    edges.push(Edge { vertices: key, faces: Vec::new() });
+                    edges.len() - 1
+                });
+                edges[id].faces.push(f);
+            }
+        }
+
+        let mut neighbors = vec![Vec::new(); vertex_count];
+        let mut creases = vec![Vec::new(); vertex_count];
+        for edge in &edges {
+            let (a, b) = edge.vertices;
+            neighbors[a].push(b);
+            neighbors[b].push(a);
+            // boundaries are kept as creases so open meshes do not shrink
+            if edge.faces.len() != 2 {
+                creases[a].push(b);
+                creases[b].push(a);
+            }
+        }
+
+        Topology {
+            edges,
+            edge_index,
+            neighbors,
+            creases
+        }
+    }
+
+    fn edge(&self, a: usize, b: usize) -> usize {
+        self.edge_index[&edge_key(a, b)]
+    }
+}
+
+fn average(positions: &[Point3], indices: &[usize]) -> Point3 {
+    indices.iter().map(|&i| positions[i]).sum::<Vec3>() / indices.len() as f64
+}
+
+fn loop_beta(n: usize) -> f64 {
+    if n == 3 {
+        3.0 / 16.0
+    } else {
+        3.0 / (8.0 * n as f64)
+    }
+}
+
+fn triangulate(faces: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    faces.iter()
+        .flat_map(|face| (1..face.len().saturating_sub(1)).map(move |i| vec![face[0], face[i], face[i + 1]]))
+        .collect()
+}
+
+fn loop_step(positions: &[Point3], faces: &[Vec<usize>]) -> (Vec<Point3>, Vec<Vec<usize>>) {
+    let topology = Topology::build(positions.len(), faces);
+
+    // even vertices
+    let mut new_positions: Vec<Point3> = positions.iter().enumerate().map(|(i, &p)| {
+        let creases = &topology.creases[i];
+        match creases.len() {
+            0 | 1 => {
+                let n = topology.neighbors[i].len();
+                let beta = loop_beta(n);
+                (1.0 - n as f64 * beta) * p + beta * topology.neighbors[i].iter().map(|&j| positions[j]).sum::<Vec3>()
+            },
+            2 => 0.75 * p + 0.125 * (positions[creases[0]] + positions[creases[1]]),
+            // corner
+            _ => p
+        }
+    }).collect();
+
+    // odd vertices
+    for edge in &topology.edges {
+        let (a, b) = edge.vertices;
+        let p = if edge.faces.len() == 2 {
+            let opposite = |f: usize| faces[f].iter().copied().find(|&v| v != a && v != b).unwrap();
+            let c = opposite(edge.faces[0]);
+            let d = opposite(edge.faces[1]);
+            0.375 * (positions[a] + positions[b]) + 0.125 * (positions[c] + positions[d])
+        } else {
+            0.5 * (positions[a] + positions[b])
+        };
+        new_positions.push(p);
+    }
+
+    let offset = positions.len();
+    let mut new_faces = Vec::with_capacity(faces.len() * 4);
+    for face in faces {
+        let (a, b, c) = (face[0], face[1], face[2]);
+        let ab = offset + topology.edge(a, b);
+        let bc = offset + topology.edge(b, c);
+        let ca = offset + topology.edge(c, a);
+        new_faces.push(vec![a, ab, ca]);
+        new_faces.push(vec![ab, b, bc]);
+        new_faces.push(vec![ca, bc, c]);
+        new_faces.push(vec![ab, bc, ca]);
+    }
+
+    (new_positions, new_faces)
+}
+
+fn catmull_clark_step(positions: &[Point3], faces: &[Vec<usize>]) -> (Vec<Point3>, Vec<Vec<usize>>) {
+    let topology = Topology::build(positions.len(), faces);
+    let face_points: Vec<Point3> = faces.iter().map(|face| average(positions, face)).collect();
+
+    let mut incident_faces = vec![Vec::new(); positions.len()];
+    for (f, face) in faces.iter().enumerate() {
+        for &v in face {
+            incident_faces[v].push(f);
+        }
+    }
+
+    // vertex points
+    let mut new_positions: Vec<Point3> = positions.iter().enumerate().map(|(i, &p)| {
+        let creases = &topology.creases[i];
+        match creases.len() {
+            0 | 1 => {
+                let n = topology.neighbors[i].len() as f64;
+                let f = incident_faces[i].iter().map(|&f| face_points[f]).sum::<Vec3>() / incident_faces[i].len() as f64;
+                let r = topology.neighbors[i].iter().map(|&j| 0.5 * (p + positions[j])).sum::<Vec3>() / n;
+                (f + 2.0 * r + (n - 3.0) * p) / n
+            },
+            2 => (positions[creases[0]] + 6.0 * p + positions[creases[1]]) / 8.0,
+            _ => p
+        }
+    }).collect();
+
+    // edge points
+    for edge in &topology.edges {
+        let (a, b) = edge.vertices;
+        let p = if edge.faces.len() == 2 {
+            0.25 * (positions[a] + positions[b] + face_points[edge.faces[0]] + face_points[edge.faces[1]])
+        } else {
+            0.5 * (positions[a] + positions[b])
+        };
+        new_positions.push(p);
+    }
+
+    let edge_offset = positions.len();
+    let face_offset = edge_offset + topology.edges.len();
+    new_positions.extend(face_points);
+
+    let mut new_faces = Vec::new();
+    for (f, face) in faces.iter().enumerate() {
+        let n = face.len();
+        for i in 0..n {
+            let prev = face[(i + n - 1) % n];
+            let curr = face[i];
+            let next = face[(i + 1) % n];
+            new_faces.push(vec![
+                curr,
+                edge_offset + topology.edge(curr, next),
+                face_offset + f,
+                edge_offset + topology.edge(prev, curr)
+            ]);
+        }
+    }
+
+    (new_positions, new_faces)
+}
+
+// push control points onto the limit surface
+fn limit_positions(positions: &[Point3], faces: &[Vec<usize>], scheme: Subdivision) -> Vec<Point3> {
+    let topology = Topology::build(positions.len(), faces);
+
+    // ring of (edge neighbor, diagonal, edge neighbor) for every quad around a vertex
+    let mut quads = vec![Vec::new(); positions.len()];
+    if let Subdivision::CatmullClark(_) = scheme {
+        for face in faces.iter().filter(|face| face.len() == 4) {
+            for i in 0..4 {
+                quads[face[i]].push((face[(i + 1) % 4], face[(i + 2) % 4], face[(i + 3) % 4]));
+            }
+        }
+    }
+
+    positions.iter().enumerate().map(|(i, &p)| {
+        let creases = &topology.creases[i];
+        let neighbors = &topology.neighbors[i];
+        match creases.len() {
+            // uniform cubic b-spline limit along the crease curve
+            2 => (positions[creases[0]] + 4.0 * p + positions[creases[1]]) / 6.0,
+            0 | 1 => {
+                let n = neighbors.len() as f64;
+                match scheme {
+                    Subdivision::Loop(_) => {
+                        let chi = 1.0 / (n + 3.0 / (8.0 * loop_beta(neighbors.len())));
+                        (1.0 - n * chi) * p + chi * neighbors.iter().map(|&j| positions[j]).sum::<Vec3>()
+                    },
+                    Subdivision::CatmullClark(_) => {
+                        if quads[i].len() != neighbors.len() {
+                            return p
+                        }
+                        // every edge neighbor is shared by two quads of the ring
+                        let edges = quads[i].iter().map(|&(a, _, b)| 0.5 * (positions[a] + positions[b])).sum::<Vec3>();
+                        let diagonals = quads[i].iter().map(|&(_, d, _)| positions[d]).sum::<Vec3>();
+                        (n * n * p + 4.0 * edges + diagonals) / (n * (n + 5.0))
+                    }
+                }
+            },
+            _ => p
+        }
+    }).collect()
+}
+
+// angle weighted average of the adjacent face normals
+fn vertex_normals(positions: &[Point3], triangles: &[[usize; 3]]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); positions.len()];
+    for tri in triangles {
+        let face_normal = (positions[tri[1]] - positions[tri[0]]).cross(positions[tri[2]] - positions[tri[0]]);
+        if face_normal.near_zero() {
+            continue;
+        }
+        let face_normal = face_normal.normalized();
+        for k in 0..3 {
+            let e1 = (positions[tri[(k + 1) % 3]] - positions[tri[k]]).normalized();
+            let e2 = (positions[tri[(k + 2) % 3]] - positions[tri[k]]).normalized();
+            let angle = e1.dot(e2).clamp(-1.0, 1.0).acos();
+            normals[tri[k]] += angle * face_normal;
+        }
+    }
+
+    normals.into_iter().map(|n| if n.near_zero() { n } else { n.normalized() }).collect()
+}
+
+/// subdivide a polygon mesh, returns limit positions, smooth normals and triangles
+pub fn subdivide(positions: Vec<Point3>, faces: Vec<Vec<usize>>, scheme: Subdivision) -> (Vec<Point3>, Vec<Vec3>, Vec<[usize; 3]>) {
+    let mut positions = positions;
+    // collapse repeated corners, degenerate faces at poles would break the topology
+    let mut faces: Vec<Vec<usize>> = faces.into_iter()
+        .map(|face| (0..face.len()).filter(|&i| face[i] != face[(i + 1) % face.len()]).map(|i| face[i]).collect::<Vec<usize>>())
+        .filter(|face| face.len() >= 3)
+        .collect();
+
+    let level = match scheme {
+        Subdivision::Loop(level) => {
+            faces = triangulate(&faces);
+            for _ in 0..level {
+                let (p, f) = loop_step(&positions, &faces);
+                positions = p;
+                faces = f;
+            }
+            level
+        },
+        Subdivision::CatmullClark(level) => {
+            for _ in 0..level {
+                let (p, f) = catmull_clark_step(&positions, &faces);
+                positions = p;
+                faces = f;
+            }
+            level
+        }
+    };
+
+    if level > 0 {
+        positions = limit_positions(&positions, &faces, scheme);
+    }
+
+    let triangles: Vec<[usize; 3]> = triangulate(&faces).into_iter().map(|tri| [tri[0], tri[1], tri[2]]).collect();
+    let normals = vertex_normals(&positions, &triangles);
+
+    (positions, normals, triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube() -> (Vec<Point3>, Vec<Vec<usize>>) {
+        let positions = vec![
+            Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, -1.0, -1.0), Point3::new(1.0, 1.0, -1.0), Point3::new(-1.0, 1.0, -1.0),
+            Point3::new(-1.0, -1.0, 1.0), Point3::new(1.0, -1.0, 1.0), Point3::new(1.0, 1.0, 1.0), Point3::new(-1.0, 1.0, 1.0)
+        ];
+        let faces = vec![
+            vec![0, 3, 2, 1], vec![4, 5, 6, 7], vec![0, 1, 5, 4],
+            vec![3, 7, 6, 2], vec![0, 4, 7, 3], vec![1, 2, 6, 5]
+        ];
+        (positions, faces)
+    }
+
+    fn octahedron() -> (Vec<Point3>, Vec<Vec<usize>>) {
+        let positions = vec![
+            Point3::new(1.0, 0.0, 0.0), Point3::new(-1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0),
+            Point3::new(0.0, -1.0, 0.0), Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, -1.0)
+        ];
+        let faces = vec![
+            vec![0, 2, 4], vec![2, 1, 4], vec![1, 3, 4], vec![3, 0, 4],
+            vec![2, 0, 5], vec![1, 2, 5], vec![3, 1, 5], vec![0, 3, 5]
+        ];
+        (positions, faces)
+    }
+
+    // largest angle between the normals of two triangles sharing an edge
+    fn sharpest_edge(positions: &[Point3], triangles: &[[usize; 3]]) -> f64 {
+        let mut normals: HashMap<(usize, usize), Vec<Vec3>> = HashMap::new();
+        for tri in triangles {
+            let n = (positions[tri[1]] - positions[tri[0]]).cross(positions[tri[2]] - positions[tri[0]]).normalized();
+            for k in 0..3 {
+                normals.entry(edge_key(tri[k], tri[(k + 1) % 3])).or_default().push(n);
+            }
+        }
+        normals.values().map(|n| n[0].dot(n[1]).clamp(-1.0, 1.0).acos()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn loop_octahedron_refines_towards_a_smooth_surface() {
+        let mut corners = Vec::new();
+        let mut sharpest = f64::consts::PI;
+        for level in 1..=4 {
+            let (positions, faces) = octahedron();
+            let (positions, normals, triangles) = subdivide(positions, faces, Subdivision::Loop(level));
+
+            // every level splits each triangle in four and adds a vertex on every edge
+            let faces = 8 * 4usize.pow(level);
+            assert_eq!(triangles.len(), faces);
+            assert_eq!(positions.len(), 2 + faces / 2);
+
+            let angle = sharpest_edge(&positions, &triangles);
+            assert!(angle < sharpest, "level {} has a sharper edge than the level before", level);
+            sharpest = angle;
+
+            // the limit surface is symmetric about the origin, its normals point away from it
+            for (p, n) in positions.iter().zip(&normals) {
+                assert!(n.dot(p.normalized()) > 0.9);
+            }
+            // the original vertices keep their indices and their limit points do not move with the level
+            corners.push(positions[..6].to_vec());
+        }
+        assert!(sharpest < 0.5);
+        for level in &corners[1..] {
+            for (a, b) in level.iter().zip(&corners[0]) {
+                assert!((*a - *b).length() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn loop_keeps_open_boundaries_in_place() {
+        // a square pyramid without its base, the rim is a boundary crease in the z = 0 plane
+        let positions = vec![
+            Point3::new(-1.0, -1.0, 0.0), Point3::new(1.0, -1.0, 0.0), Point3::new(1.0, 1.0, 0.0),
+            Point3::new(-1.0, 1.0, 0.0), Point3::new(0.0, 0.0, 1.0)
+        ];
+        let faces = vec![vec![0, 1, 4], vec![1, 2, 4], vec![2, 3, 4], vec![3, 0, 4]];
+        let (positions, _, triangles) = subdivide(positions, faces, Subdivision::Loop(3));
+
+        assert_eq!(triangles.len(), 4 * 64);
+        // the rim is refined into 4 * 2^3 vertices that stay on the rim, the inside rises off it
+        let rim = positions.iter().filter(|p| p.z().abs() < 1e-12).count();
+        assert_eq!(rim, 32);
+        for p in positions.iter().filter(|p| p.z().abs() < 1e-12) {
+            assert!(p.x().abs() <= 1.0 && p.y().abs() <= 1.0);
+            assert!(p.x().abs().max(p.y().abs()) > 0.5);
+        }
+        assert!(positions.iter().all(|p| p.z() > -1e-12));
+    }
+
+    #[test]
+    fn catmull_clark_cube_stays_closed() {
+        let (positions, faces) = cube();
+        let (_, _, triangles) = subdivide(positions, faces, Subdivision::CatmullClark(3));
+
+        // every directed edge is matched by the opposite one of exactly one neighbor
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for tri in &triangles {
+            for k in 0..3 {
+                *edges.entry((tri[k], tri[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {} {} is used {} times", a, b, count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {} {} has no opposite", a, b);
+        }
+        assert_eq!(triangles.len(), 6 * 4 * 4 * 4 * 2);
+    }
+
+    #[test]
+    fn catmull_clark_cube_lies_inside_the_cage() {
+        let (positions, faces) = cube();
+        let (positions, normals, _) = subdivide(positions, faces, Subdivision::CatmullClark(3));
+
+        for (p, n) in positions.iter().zip(&normals) {
+            for axis in 0..3 {
+                assert!(p[axis].abs() <= 1.0, "{} {} {} leaves the control hull", p.x(), p.y(), p.z());
+            }
+            // a convex cage gives a convex limit surface with outward normals
+            assert!(n.dot(*p) > 0.0);
+        }
+        // the corners are pulled in, the face centers stay close to the cage
+        let radius = |p: &Point3| p.length();
+        let (near, far) = positions.iter().fold((f64::MAX, 0.0f64), |(lo, hi), p| (lo.min(radius(p)), hi.max(radius(p))));
+        assert!(near > 0.7 && far < 3.0f64.sqrt());
+    }
+}
@@ -1,7 +1,8 @@
-use super::vec::Vec3;
+use super::vec::{Vec3, Color};
 use super::ray::Ray;
-use super::hit::{Hittable, HitRecord};
+use super::hit::{Hittable, HitRecord, SurfaceSample};
 use super::aabb::AABB;
+use super::light_bvh::LightBounds;
 
 #[derive(Clone)]
 pub struct Translate<H: Hittable> {
@@ -29,6 +30,11 @@ impl<H: Hittable> Hittable for Translate<H> {
         )
     }
 
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> Color {
+        let translated_ray = Ray::new(r.origin() - self.offset, r.direction(), r.time());
+        self.hittable.transmittance(&translated_ray, t_min, t_max)
+    }
+
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.hittable.bounding_box(t0, t1).map(
             |mut aabb| {
@@ -38,4 +44,31 @@ impl<H: Hittable> Hittable for Translate<H> {
             }
         )
     }
+
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.hittable.pdf_value(o - self.offset, v)
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.hittable.random(o - self.offset)
+    }
+
+    fn power(&self) -> f64 {
+        self.hittable.power()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        self.hittable.light_bounds().map(|mut bounds| {
+            bounds.bbox.min += self.offset;
+            bounds.bbox.max += self.offset;
+            bounds
+        })
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<SurfaceSample> {
+        self.hittable.sample_surface(time).map(|mut sample| {
+            sample.position += self.offset;
+            sample
+        })
+    }
 }
\ No newline at end of file
@@ -1,13 +1,17 @@
 use std::f64;
-use super::vec::{Vec3, Point3};
+use rand::Rng;
+use super::vec::{Vec3, Point3, Color};
 use super::ray::Ray;
-use super::hit::{Hittable, HitRecord};
+use super::hit::{Hittable, HitRecord, SurfaceSample};
 use super::mat::Material;
 use super::aabb::AABB;
+use super::light_bvh::LightBounds;
 
 #[derive(Clone)]
 pub struct Triangle<M: Material> {
     vertices: [Point3; 3],
+    // per vertex shading normals
+    normals: Option<[Vec3; 3]>,
     material: M
 }
 
@@ -15,9 +19,22 @@ impl<M: Material> Triangle<M> {
     pub fn new(vertices: [Point3; 3], material: M) -> Triangle<M> {
         Triangle {
             vertices,
+            normals: None,
             material
         }
     }
+
+    pub fn with_normals(vertices: [Point3; 3], normals: [Vec3; 3], material: M) -> Triangle<M> {
+        Triangle {
+            vertices,
+            normals: Some(normals),
+            material
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        0.5 * (self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]).length()
+    }
 }
 
 impl<M: Material> Hittable for Triangle<M> {
@@ -48,9 +65,15 @@ impl<M: Material> Hittable for Triangle<M> {
                     u: b1,
                     v: b2,
                     front_face: false,
+                    weight: Color::new(1.0, 1.0, 1.0),
                     material: &self.material
                 };
                 rec.set_face_normal(r, normal);
+                if let Some(normals) = self.normals {
+                    // interpolate shading normal, kept on the side of the geometric one
+                    let shading = ((1.0 - b1 - b2) * normals[0] + b1 * normals[1] + b2 * normals[2]).normalized();
+                    rec.normal = if shading.dot(rec.normal) < 0.0 { (-1.0) * shading } else { shading };
+                }
                 Some(rec)
             }
         }
@@ -64,9 +87,58 @@ impl<M: Material> Hittable for Triangle<M> {
         let max_y = self.vertices[0].y().max(f64::max(self.vertices[1].y(), self.vertices[2].y()));
         let max_z = self.vertices[0].z().max(f64::max(self.vertices[1].z(), self.vertices[2].z()));
 
-        let min = Vec3::new(min_x, min_y, min_z);
-        let max = Vec3::new(max_x, max_y, max_z);
+        // axis aligned triangles are flat boxes, pad every axis a small amount so rays do not miss them
+        let min = Vec3::new(min_x, min_y, min_z) - Vec3::new(0.0001, 0.0001, 0.0001);
+        let max = Vec3::new(max_x, max_y, max_z) + Vec3::new(0.0001, 0.0001, 0.0001);
 
         Some(AABB::new(min, max))
     }
-}
\ No newline at end of file
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        if let Some(rec) = self.hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY) {
+            // geometric normal, the shading normal would bias the jacobian
+            let normal = (self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]).normalized();
+            let distance_squared = rec.t.powi(2) * v.length().powi(2);
+            let cosine = v.dot(normal).abs() / v.length();
+            if cosine != 0.0 { distance_squared / (cosine * self.area()) } else { 0.0 }
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        // uniform barycentric sampling
+        let mut rng = rand::thread_rng();
+        let su0 = rng.gen::<f64>().sqrt();
+        let b0 = 1.0 - su0;
+        let b1 = rng.gen::<f64>() * su0;
+        let random_point = b0 * self.vertices[0] + b1 * self.vertices[1] + (1.0 - b0 - b1) * self.vertices[2];
+        random_point - o
+    }
+
+    fn power(&self) -> f64 {
+        let sides = if self.material.emits_both_sides() { 2.0 } else { 1.0 };
+        sides * f64::consts::PI * self.area() * self.material.emitted_luminance()
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        let normal = (self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]);
+        self.bounding_box(0.0, 1.0).map(|bbox| if self.material.emits_both_sides() {
+            LightBounds::omnidirectional(bbox, self.power())
+        } else {
+            LightBounds::one_sided(bbox, self.power(), normal)
+        })
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<SurfaceSample> {
+        let mut rng = rand::thread_rng();
+        let su0 = rng.gen::<f64>().sqrt();
+        let b0 = 1.0 - su0;
+        let b1 = rng.gen::<f64>() * su0;
+        Some(SurfaceSample {
+            position: b0 * self.vertices[0] + b1 * self.vertices[1] + (1.0 - b0 - b1) * self.vertices[2],
+            normal: (self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]).normalized(),
+            pdf: 1.0 / self.area()
+        })
+    }
+}
@@ -122,6 +122,10 @@ impl Vec3 {
 
     // color utility functions
 
+    pub fn luminance(self) -> f64 {
+        0.2126 * self[0] + 0.7152 * self[1] + 0.0722 * self[2]
+    }
+
     pub fn format_color(self, samples_per_pixel: u64) -> String {     
         let ir = (256.0 * (self[0] / (samples_per_pixel as f64)).sqrt().clamp(0.0, 0.999)) as u64;
         let ig = (256.0 * (self[1] / (samples_per_pixel as f64)).sqrt().clamp(0.0, 0.999)) as u64;